@@ -0,0 +1,18 @@
+fn main() {
+    #[cfg(feature = "header")]
+    generate_header();
+}
+
+/// The header is committed, so that it is only regenerated on demand by `cargo build --features header`.
+#[cfg(feature = "header")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap())
+        .generate()
+        .expect("Unable to generate C header")
+        .write_to_file(format!("{}/include/gitnote.h", crate_dir));
+}
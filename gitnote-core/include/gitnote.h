@@ -0,0 +1,85 @@
+/*
+ * C ABI of git-note core.
+ *
+ * Every function takes UTF-8, NUL-terminated strings and returns a JSON of {"exit_code": int, "text": string}.
+ * The returned string is owned by the caller, and must be released by `gitnote_free`.
+ * `line` starts from 1, and 0 targets the whole file or directory.
+ * `content` is the unsaved content of the file, which may be NULL to read the file on disk.
+ */
+
+#ifndef GITNOTE_H
+#define GITNOTE_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit manually, but run `cargo build --features header`. */
+
+#include <stdint.h>
+
+/**
+ * Adds a note to the line of file.
+ *
+ * # Safety
+ *
+ * Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+ */
+char *gitnote_add(const char *exec_path,
+                  const char *file_path,
+                  int32_t line,
+                  const char *message,
+                  const char *content);
+
+/**
+ * Reads the note of file, with messages anchored to the current content.
+ *
+ * # Safety
+ *
+ * Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+ */
+char *gitnote_read(const char *exec_path,
+                   const char *file_path,
+                   const char *content);
+
+/**
+ * Replaces the message on the line of file.
+ *
+ * # Safety
+ *
+ * Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+ */
+char *gitnote_edit(const char *exec_path,
+                   const char *file_path,
+                   int32_t line,
+                   const char *message,
+                   const char *content);
+
+/**
+ * Deletes the message on the line of file.
+ *
+ * # Safety
+ *
+ * Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+ */
+char *gitnote_delete(const char *exec_path,
+                     const char *file_path,
+                     int32_t line,
+                     const char *content);
+
+/**
+ * Lists every note under `path`, which is either a file or a directory.
+ *
+ * # Safety
+ *
+ * Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+ */
+char *gitnote_list(const char *exec_path,
+                   const char *path);
+
+/**
+ * Releases a string returned by the other functions. Passing null is a no-op.
+ *
+ * # Safety
+ *
+ * `response` must be either null or a string returned by the other functions, which is not released yet.
+ */
+void gitnote_free(char *response);
+
+#endif /* GITNOTE_H */
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::path::Paths;
+
+/// The state of an attribute for a path, as described in `gitattributes(5)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+/// A minimal reader of `.gitattributes`, enough to look up attributes of a single path.
+///
+/// Attributes are read from every `.gitattributes` between the root and the directory of the path,
+/// and then from `.git/info/attributes`, where the latter one takes precedence.
+/// Macros other than the built-in `binary` are not supported.
+#[derive(Debug)]
+pub struct GitAttributes {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    base: PathBuf,
+    pattern: String,
+    attrs: Vec<(String, Option<AttrValue>)>,
+}
+
+impl GitAttributes {
+    pub fn load(paths: &Paths) -> anyhow::Result<Self> {
+        let root = paths.root();
+        let mut rules = Vec::new();
+
+        let mut base = PathBuf::new();
+        Self::read_rules(&root.join(".gitattributes"), &base, &mut rules)?;
+        if let Some(parent) = paths.relative().parent() {
+            for component in parent.components() {
+                base.push(component);
+                Self::read_rules(&root.join(&base).join(".gitattributes"), &base, &mut rules)?;
+            }
+        }
+        Self::read_rules(&root.join(".git/info/attributes"), &PathBuf::new(), &mut rules)?;
+        Ok(Self { rules })
+    }
+
+    fn read_rules(file: &Path, base: &Path, rules: &mut Vec<Rule>) -> anyhow::Result<()> {
+        if !file.is_file() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(file)?;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let pattern = match tokens.next() {
+                Some(pattern) => pattern.to_string(),
+                None => continue,
+            };
+            let attrs = tokens.flat_map(Self::parse_attr).collect();
+            rules.push(Rule { base: base.to_path_buf(), pattern, attrs });
+        }
+        Ok(())
+    }
+
+    fn parse_attr(token: &str) -> Vec<(String, Option<AttrValue>)> {
+        let (name, value) = if let Some(name) = token.strip_prefix('-') {
+            (name, Some(AttrValue::Unset))
+        } else if let Some(name) = token.strip_prefix('!') {
+            (name, None)
+        } else if let Some((name, value)) = token.split_once('=') {
+            (name, Some(AttrValue::Value(value.to_string())))
+        } else {
+            (token, Some(AttrValue::Set))
+        };
+
+        let mut attrs = vec![(name.to_string(), value.clone())];
+        if name == "binary" && value == Some(AttrValue::Set) {
+            // built-in macro : `binary` is `-diff -merge -text`
+            for expanded in ["diff", "merge", "text"] {
+                attrs.push((expanded.to_string(), Some(AttrValue::Unset)));
+            }
+        }
+        attrs
+    }
+
+    /// Looks up the attribute of given path, which is relative from the root of the repository.
+    pub fn get(&self, relative: &Path, name: &str) -> Option<AttrValue> {
+        let mut found = None;
+        for rule in &self.rules {
+            if !rule.matches(relative) {
+                continue;
+            }
+            for (attr, value) in &rule.attrs {
+                if attr == name {
+                    found = value.clone();
+                }
+            }
+        }
+        found
+    }
+}
+
+impl Rule {
+    fn matches(&self, relative: &Path) -> bool {
+        let Ok(path) = relative.strip_prefix(&self.base) else {
+            return false;
+        };
+        let path = path.to_string_lossy().replace('\\', "/");
+        let pattern = self.pattern.strip_prefix('/').unwrap_or(&self.pattern);
+        if self.pattern.contains('/') {
+            return glob_match(pattern.as_bytes(), path.as_bytes());
+        }
+        let basename = path.rsplit('/').next().unwrap_or(&path);
+        glob_match(pattern.as_bytes(), basename.as_bytes())
+    }
+}
+
+/// Matches a glob pattern, where `*` and `?` never match `/` but `**` does.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => text.first().is_some_and(|c| *c != b'/') && glob_match(&pattern[1..], &text[1..]),
+        Some(b'[') => {
+            let Some(end) = pattern.iter().skip(1).position(|c| *c == b']').map(|i| i + 1) else {
+                return text.first() == Some(&b'[') && glob_match(&pattern[1..], &text[1..]);
+            };
+            let Some(c) = text.first() else {
+                return false;
+            };
+            let class = &pattern[1..end];
+            let (negated, class) = match class.first() {
+                Some(b'!') | Some(b'^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= *c && *c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == *c;
+                    i += 1;
+                }
+            }
+            matched != negated && glob_match(&pattern[end + 1..], &text[1..])
+        }
+        Some(p) => text.first() == Some(p) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::path::PathResolver;
+    use crate::testlib::TestRepo;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(b"*.txt", b"foo.txt"));
+        assert!(!glob_match(b"*.txt", b"foo/bar.txt"));
+        assert!(glob_match(b"docs/**/*.md", b"docs/a/b/c.md"));
+        assert!(glob_match(b"**/legacy.rs", b"legacy.rs"));
+        assert!(glob_match(b"file[0-9].?s", b"file1.rs"));
+        assert!(!glob_match(b"file[!0-9].rs", b"file1.rs"));
+    }
+
+    #[test]
+    fn lookup() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file(".gitattributes", Some("*.txt working-tree-encoding=euc-kr\n*.png binary\n"))?;
+        repo.create_dir("sub")?;
+        repo.create_file("sub/.gitattributes", Some("*.txt -working-tree-encoding\n"))?;
+        repo.create_file("sub/foo.txt", Some("foo"))?;
+
+        // when
+        let paths = PathResolver::resolve(repo.path(), "sub/foo.txt")?;
+        let attributes = GitAttributes::load(&paths)?;
+
+        // then
+        assert_eq!(attributes.get(Path::new("bar.txt"), "working-tree-encoding"), Some(AttrValue::Value("euc-kr".to_string())));
+        assert_eq!(attributes.get(Path::new("sub/foo.txt"), "working-tree-encoding"), Some(AttrValue::Unset));
+        assert_eq!(attributes.get(Path::new("image.png"), "text"), Some(AttrValue::Unset));
+        assert_eq!(attributes.get(Path::new("image.png"), "working-tree-encoding"), None);
+        Ok(())
+    }
+}
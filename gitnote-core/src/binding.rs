@@ -0,0 +1,78 @@
+//! Pieces shared by bindings for other languages, which are JNI, C ABI and python.
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::handlers::{NoteArgs, NoteHandler};
+use crate::libgit::{libgit_of, Libgit};
+use crate::note::Scope;
+use crate::path::Paths;
+use crate::repository::NoteRepository;
+
+#[repr(C)]
+#[derive(Serialize)]
+pub struct Response {
+    pub exit_code: i32,
+    pub text: String,
+}
+
+impl Response {
+    /// Successful result goes to `text` as is, and an error goes with its causes and a non-zero exit code.
+    pub fn of(result: anyhow::Result<String>) -> Self {
+        match result {
+            Ok(text) => Response { exit_code: 0, text },
+            Err(e) => Response { exit_code: 1, text: format!("{:#}", e) },
+        }
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self {
+            exit_code: 0,
+            text: "".to_string(),
+        }
+    }
+}
+
+pub struct NoteLibArgs {
+    pub paths: Paths,
+    pub line: Option<i32>,
+    pub message: Option<String>,
+    pub content: Option<String>,
+}
+
+impl NoteArgs for NoteLibArgs {
+    fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    fn user_line(&self) -> usize {
+        self.line.unwrap() as usize
+    }
+
+    fn sys_line(&self) -> usize {
+        self.line.unwrap() as usize - 1
+    }
+
+    fn message(&self) -> String {
+        self.message.as_ref().unwrap().clone()
+    }
+
+    fn scope(&self) -> Scope {
+        match self.line {
+            Some(_) => Scope::Line,
+            None => Scope::File,
+        }
+    }
+
+    fn content(&self) -> Option<String> {
+        self.content.clone()
+    }
+}
+
+/// Fails if the config of repository is unreadable, which goes back to the caller rather than panicking across the boundary.
+pub fn note_handler(paths: &Paths) -> anyhow::Result<NoteHandler<Box<dyn Libgit>>> {
+    let config = Config::load(paths)?;
+    Ok(NoteHandler::new(NoteRepository::new(libgit_of(config.libgit()))))
+}
@@ -1,27 +1,87 @@
+use std::cell::OnceCell;
 use std::env;
+use std::io;
+use std::path::PathBuf;
 use std::str::FromStr;
 
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use anyhow::Context;
 use clap::{Args, Parser, Subcommand};
 
-use crate::handlers::NoteArgs;
+use crate::config::{LibgitBackend, Storage};
+use crate::export::ExportFormat;
+use crate::gitnotes::GIT_NOTES_REF;
+use crate::handlers::{NoteArgs, NoteQuery};
+use crate::note::Scope;
 use crate::path::{PathResolver, Paths};
 
 #[derive(Debug, Parser)]
 pub struct CliCommand {
     #[clap(subcommand)]
     pub sub: CliSubcommand,
+    #[arg(
+        long,
+        global = true,
+        value_name = "BACKEND",
+        help = "Specifies how to access git repository, either `manual` or `process`. Overrides `libgit` in config",
+        value_parser = clap::value_parser!(LibgitBackend)
+    )]
+    pub libgit: Option<LibgitBackend>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum CliSubcommand {
     Add(AddArgs),
     Read(ReadArgs),
+    /// Reads notes of a file along with the commit, author and date which last changed each line
+    Blame(BlameArgs),
     Edit(EditArgs),
     Delete(DeleteArgs),
+    List(ListArgs),
+    /// Writes a report of every note with the code around it, e.g. to walk someone through the codebase
+    Export(ExportArgs),
+    /// Shows notes in or near every hunk of `git diff`, flagging the ones the change invalidates
+    Diff(DiffArgs),
+    /// Turns marker comments in source, e.g. `// NOTE(jane): explains why`, into notes at the lines they describe
+    ImportComments(ImportCommentsArgs),
+    /// Prints a patch which writes notes into source as marker comments, to be applied by `git apply`
+    Inline(InlineArgs),
+    /// Runs a language server over stdio, which shows notes as hovers and diagnostics
+    Lsp(LspArgs),
+    /// Runs a server over stdio, which accepts newline-delimited JSON-RPC requests for IDE clients
+    Serve(ServeArgs),
+    /// Rewrites every note file of the repository in the latest format
+    Migrate(MigrateArgs),
+    /// Moves every note of the repository into another storage, and switches `storage` in config
+    Convert(ConvertArgs),
+    /// Exchanges notes with the notes of git itself, which are attached to commits
+    #[clap(subcommand)]
+    GitNotes(CliGitNotesSubcommand),
     #[clap(subcommand)]
     Config(CliConfigSubcommand),
 }
 
+impl CliSubcommand {
+    /// What the command is about, which is the repository of the current directory if it takes no path.
+    /// Config of the repository owning it is taken, e.g. to choose the backend of git.
+    pub fn paths(&self) -> anyhow::Result<Paths> {
+        let paths = match self {
+            CliSubcommand::Add(args) => &args.paths,
+            CliSubcommand::Read(args) => &args.paths,
+            CliSubcommand::Blame(args) => &args.paths,
+            CliSubcommand::Edit(args) => &args.paths,
+            CliSubcommand::Delete(args) => &args.paths,
+            CliSubcommand::List(args) => &args.path,
+            CliSubcommand::Export(args) => &args.path,
+            CliSubcommand::Diff(args) => &args.path,
+            CliSubcommand::Inline(args) => &args.path,
+            CliSubcommand::ImportComments(args) if !args.path.is_empty() => &args.path[0],
+            _ => return PathResolver::resolve(env::current_dir()?, "."),
+        };
+        Ok(paths.clone())
+    }
+}
+
 /// for clap parser.
 impl FromStr for Paths {
     type Err = anyhow::Error;
@@ -45,15 +105,37 @@ pub struct AddArgs {
     #[arg(
         short,
         long,
-        help = "Specifies the line number to add a note to. The line number starts from 1"
+        help = "Specifies the line number to add a note to. The line number starts from 1. Omit it to add a note to the whole file"
     )]
-    line: usize,
+    line: Option<usize>,
     #[arg(
         short,
         long,
         help = "The note message"
     )]
     message: String,
+    #[arg(
+        long,
+        help = "Reads the current content of the file from stdin, e.g. an unsaved buffer of editor, to anchor lines to",
+        default_value = "false"
+    )]
+    stdin: bool,
+    #[arg(
+        short,
+        long = "tag",
+        value_name = "TAG",
+        help = "Tags the note, e.g. `todo`. Can be repeated"
+    )]
+    tags: Vec<String>,
+    #[arg(skip)]
+    buffer: OnceCell<String>,
+}
+
+impl AddArgs {
+    /// Reads the content from stdin if `--stdin` is given, which [`NoteArgs::content`] returns afterwards.
+    pub fn read_stdin(&self) -> anyhow::Result<()> {
+        stdin_content(self.stdin, &self.buffer)
+    }
 }
 
 impl NoteArgs for AddArgs {
@@ -62,16 +144,28 @@ impl NoteArgs for AddArgs {
     }
 
     fn user_line(&self) -> usize {
-        self.line
+        self.line.expect("line is not given for a file note")
     }
 
     fn sys_line(&self) -> usize {
-        self.line - 1
+        self.user_line() - 1
     }
 
     fn message(&self) -> String {
         self.message.clone()
     }
+
+    fn scope(&self) -> Scope {
+        line_scope(self.line)
+    }
+
+    fn content(&self) -> Option<String> {
+        self.buffer.get().cloned()
+    }
+
+    fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
 }
 
 #[derive(Debug, Args)]
@@ -90,6 +184,21 @@ pub struct ReadArgs {
         default_value = "false"
     )]
     pub formatted: bool,
+    #[arg(
+        long,
+        help = "Reads the current content of the file from stdin, e.g. an unsaved buffer of editor, to anchor lines to",
+        default_value = "false"
+    )]
+    stdin: bool,
+    #[arg(skip)]
+    buffer: OnceCell<String>,
+}
+
+impl ReadArgs {
+    /// Reads the content from stdin if `--stdin` is given, which [`NoteArgs::content`] returns afterwards.
+    pub fn read_stdin(&self) -> anyhow::Result<()> {
+        stdin_content(self.stdin, &self.buffer)
+    }
 }
 
 impl NoteArgs for ReadArgs {
@@ -108,6 +217,59 @@ impl NoteArgs for ReadArgs {
     fn message(&self) -> String {
         unreachable!("message is not used in read operation")
     }
+
+    fn content(&self) -> Option<String> {
+        self.buffer.get().cloned()
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct BlameArgs {
+    #[arg(
+        short = 'f',
+        long = "file",
+        value_name = "FILE",
+        help = "Specifies the file to blame",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub paths: Paths,
+    #[arg(
+        long,
+        help = "Reads the current content of the file from stdin, e.g. an unsaved buffer of editor, to anchor lines to",
+        default_value = "false"
+    )]
+    stdin: bool,
+    #[arg(skip)]
+    buffer: OnceCell<String>,
+}
+
+impl BlameArgs {
+    /// Reads the content from stdin if `--stdin` is given, which [`NoteArgs::content`] returns afterwards.
+    pub fn read_stdin(&self) -> anyhow::Result<()> {
+        stdin_content(self.stdin, &self.buffer)
+    }
+}
+
+impl NoteArgs for BlameArgs {
+    fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    fn user_line(&self) -> usize {
+        unreachable!("user_line is not used in blame operation")
+    }
+
+    fn sys_line(&self) -> usize {
+        unreachable!("sys_line is not used in blame operation")
+    }
+
+    fn message(&self) -> String {
+        unreachable!("message is not used in blame operation")
+    }
+
+    fn content(&self) -> Option<String> {
+        self.buffer.get().cloned()
+    }
 }
 
 #[derive(Debug, Args)]
@@ -123,15 +285,30 @@ pub struct EditArgs {
     #[arg(
         short,
         long,
-        help = "Specifies the line number to edit a note to. The line number starts from 1"
+        help = "Specifies the line number to edit a note to. The line number starts from 1. Omit it to edit the note of the whole file"
     )]
-    pub line: usize,
+    pub line: Option<usize>,
     #[arg(
         short,
         long,
         help = "Specifies new note message to override previous one"
     )]
     pub message: String,
+    #[arg(
+        long,
+        help = "Reads the current content of the file from stdin, e.g. an unsaved buffer of editor, to anchor lines to",
+        default_value = "false"
+    )]
+    stdin: bool,
+    #[arg(skip)]
+    buffer: OnceCell<String>,
+}
+
+impl EditArgs {
+    /// Reads the content from stdin if `--stdin` is given, which [`NoteArgs::content`] returns afterwards.
+    pub fn read_stdin(&self) -> anyhow::Result<()> {
+        stdin_content(self.stdin, &self.buffer)
+    }
 }
 
 impl NoteArgs for EditArgs {
@@ -140,16 +317,24 @@ impl NoteArgs for EditArgs {
     }
 
     fn user_line(&self) -> usize {
-        self.line
+        self.line.expect("line is not given for a file note")
     }
 
     fn sys_line(&self) -> usize {
-        self.line - 1
+        self.user_line() - 1
     }
 
     fn message(&self) -> String {
         self.message.clone()
     }
+
+    fn scope(&self) -> Scope {
+        line_scope(self.line)
+    }
+
+    fn content(&self) -> Option<String> {
+        self.buffer.get().cloned()
+    }
 }
 
 #[derive(Debug, Args)]
@@ -165,9 +350,24 @@ pub struct DeleteArgs {
     #[arg(
         short,
         long,
-        help = "Specifies the line number to delete. The line number starts from 1"
+        help = "Specifies the line number to delete. The line number starts from 1. Omit it to delete the note of the whole file"
     )]
-    pub line: usize,
+    pub line: Option<usize>,
+    #[arg(
+        long,
+        help = "Reads the current content of the file from stdin, e.g. an unsaved buffer of editor, to anchor lines to",
+        default_value = "false"
+    )]
+    stdin: bool,
+    #[arg(skip)]
+    buffer: OnceCell<String>,
+}
+
+impl DeleteArgs {
+    /// Reads the content from stdin if `--stdin` is given, which [`NoteArgs::content`] returns afterwards.
+    pub fn read_stdin(&self) -> anyhow::Result<()> {
+        stdin_content(self.stdin, &self.buffer)
+    }
 }
 
 impl NoteArgs for DeleteArgs {
@@ -176,16 +376,362 @@ impl NoteArgs for DeleteArgs {
     }
 
     fn user_line(&self) -> usize {
-        self.line
+        self.line.expect("line is not given for a file note")
     }
 
     fn sys_line(&self) -> usize {
-        self.line - 1
+        self.user_line() - 1
     }
 
     fn message(&self) -> String {
         unreachable!("message is not used in delete operation")
     }
+
+    fn scope(&self) -> Scope {
+        line_scope(self.line)
+    }
+
+    fn content(&self) -> Option<String> {
+        self.buffer.get().cloned()
+    }
+}
+
+/// Reads stdin into `buffer` if asked, only once even though it is called several times.
+/// It is called before the command runs, so that a failure is reported as an error of the command.
+fn stdin_content(stdin: bool, buffer: &OnceCell<String>) -> anyhow::Result<()> {
+    if !stdin || buffer.get().is_some() {
+        return Ok(());
+    }
+    let content = io::read_to_string(io::stdin()).context("Failed to read stdin")?;
+    let _ = buffer.set(content);
+    return Ok(());
+}
+
+/// A note without line targets the whole file.
+fn line_scope(line: Option<usize>) -> Scope {
+    match line {
+        Some(_) => Scope::Line,
+        None => Scope::File,
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Lists notes only under the path",
+        default_value = ".",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub path: Paths,
+    #[arg(
+        long,
+        help = "Prints the notes in a json-formatted way",
+        default_value = "false"
+    )]
+    pub formatted: bool,
+    #[arg(
+        short,
+        long,
+        help = "Lists only notes with the tag"
+    )]
+    pub tag: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "Lists only notes written by the author, matching a part of name or email"
+    )]
+    pub author: Option<String>,
+    #[arg(
+        long,
+        value_name = "DATE",
+        help = "Lists only notes written at or after the date, e.g. `2024-05-01` or `2024-05-01T09:00:00+09:00`",
+        value_parser = since_date
+    )]
+    pub since: Option<DateTime<Utc>>,
+    #[arg(
+        long,
+        value_name = "DATE",
+        help = "Lists only notes written at or before the date, which includes the whole day if only a date is given",
+        value_parser = until_date
+    )]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl NoteArgs for ListArgs {
+    fn paths(&self) -> &Paths {
+        &self.path
+    }
+
+    fn user_line(&self) -> usize {
+        unreachable!("user_line is not used in list operation")
+    }
+
+    fn sys_line(&self) -> usize {
+        unreachable!("sys_line is not used in list operation")
+    }
+
+    fn message(&self) -> String {
+        unreachable!("message is not used in list operation")
+    }
+
+    fn query(&self) -> NoteQuery {
+        NoteQuery {
+            tag: self.tag.clone(),
+            author: self.author.clone(),
+            since: self.since,
+            until: self.until,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Exports notes only under the path",
+        default_value = ".",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub path: Paths,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        help = "Specifies the format of report, either `md`, `html`, `csv` or `json`. The html one is a single page without any other file",
+        default_value = "md",
+        value_parser = clap::value_parser!(ExportFormat)
+    )]
+    pub format: ExportFormat,
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "LINES",
+        help = "Specifies how many lines of code before and after a noted line to include",
+        default_value = "2"
+    )]
+    pub context: usize,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Writes the report into the file instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Writes notes on lines as review comments of GitHub or GitLab in json instead, which are anchored to the files at HEAD",
+        default_value = "false",
+        conflicts_with_all = ["format", "context"]
+    )]
+    pub review: bool,
+}
+
+impl NoteArgs for ExportArgs {
+    fn paths(&self) -> &Paths {
+        &self.path
+    }
+
+    fn user_line(&self) -> usize {
+        unreachable!("user_line is not used in export operation")
+    }
+
+    fn sys_line(&self) -> usize {
+        unreachable!("sys_line is not used in export operation")
+    }
+
+    fn message(&self) -> String {
+        unreachable!("message is not used in export operation")
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[arg(
+        value_name = "RANGE",
+        help = "Compares the working tree with the index if omitted, with a commit if `A` is given, or two commits if `A..B` is given"
+    )]
+    pub range: Option<String>,
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Shows notes only under the path",
+        default_value = ".",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub path: Paths,
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "LINES",
+        help = "Specifies how many lines around a change are taken as near it",
+        default_value = "3"
+    )]
+    pub context: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportCommentsArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Imports comments of the file, or every file under the directory except hidden ones. Can be repeated",
+        default_value = ".",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub path: Vec<Paths>,
+    #[arg(
+        long,
+        value_name = "MARKER",
+        help = "The word a comment starts with to be imported",
+        default_value = "NOTE"
+    )]
+    pub marker: String,
+    #[arg(
+        long,
+        help = "Removes imported comments from source",
+        default_value = "false"
+    )]
+    pub strip: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct InlineArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "Inlines notes only under the path",
+        default_value = ".",
+        value_parser = clap::value_parser!(Paths)
+    )]
+    pub path: Paths,
+    #[arg(
+        long,
+        value_name = "MARKER",
+        help = "The word comments start with, which `import-comments` reads back",
+        default_value = "NOTE"
+    )]
+    pub marker: String,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Writes the patch into the file instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+impl NoteArgs for InlineArgs {
+    fn paths(&self) -> &Paths {
+        &self.path
+    }
+
+    fn user_line(&self) -> usize {
+        unreachable!("user_line is not used in inline operation")
+    }
+
+    fn sys_line(&self) -> usize {
+        unreachable!("sys_line is not used in inline operation")
+    }
+
+    fn message(&self) -> String {
+        unreachable!("message is not used in inline operation")
+    }
+}
+
+fn since_date(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    parse_date(s, NaiveTime::MIN)
+}
+
+fn until_date(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    parse_date(s, NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+}
+
+/// Takes either RFC 3339, or a date in UTC which is completed with given time.
+fn parse_date(s: &str, time: NaiveTime) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| anyhow::anyhow!("`{}` is neither a date like `2024-05-01` nor RFC 3339", s))?;
+    Ok(date.and_time(time).and_utc())
+}
+
+#[derive(Debug, Args)]
+pub struct LspArgs {
+    #[arg(
+        long,
+        help = "Communicates over stdio, which is the only transport supported. Accepted as editors pass it by default",
+        default_value = "true"
+    )]
+    pub stdio: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    #[arg(
+        long,
+        value_name = "STORAGE",
+        help = "The storage to move notes into, either `files`, `log` or `tree`. Converting a log into a log compacts it",
+        value_parser = clap::value_parser!(Storage)
+    )]
+    pub to: Storage,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[arg(
+        long,
+        help = "Communicates over stdio, which is the only transport supported",
+        default_value = "true"
+    )]
+    pub stdio: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliGitNotesSubcommand {
+    /// Summarises notes on lines changed by a commit into a note of git, which `git log --notes=gitnote` shows
+    Export(GitNotesExportArgs),
+    /// Reads back notes of git written by `export`, as notes on the lines at those commits
+    Import(GitNotesImportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GitNotesExportArgs {
+    #[arg(
+        long,
+        value_name = "REV",
+        help = "Specifies the commit to attach the note to",
+        default_value = "HEAD"
+    )]
+    pub rev: String,
+    #[arg(
+        long = "ref",
+        value_name = "REF",
+        help = "Specifies the notes ref of git, which is `refs/notes/<REF>`",
+        default_value = GIT_NOTES_REF
+    )]
+    pub notes_ref: String,
+}
+
+#[derive(Debug, Args)]
+pub struct GitNotesImportArgs {
+    #[arg(
+        long = "ref",
+        value_name = "REF",
+        help = "Specifies the notes ref of git, which is `refs/notes/<REF>`",
+        default_value = GIT_NOTES_REF
+    )]
+    pub notes_ref: String,
 }
 
 #[derive(Debug, Subcommand)]
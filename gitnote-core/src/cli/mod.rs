@@ -1,10 +1,14 @@
 use colored::Colorize;
 use unicode_width::UnicodeWidthStr;
 
-use crate::cli::argument::{AddArgs, DeleteArgs, EditArgs, ReadArgs};
-use crate::handlers::{NoteArgs, NoteHandler};
-use crate::libgit::Libgit;
-use crate::note::{Message, Note};
+use crate::cli::argument::{AddArgs, BlameArgs, DeleteArgs, DiffArgs, EditArgs, ConvertArgs, ExportArgs, GitNotesExportArgs, GitNotesImportArgs, ImportCommentsArgs, InlineArgs, ListArgs, LspArgs, MigrateArgs, ReadArgs, ServeArgs};
+use crate::export::render;
+use crate::handlers::{NoteArgs, NoteHandler, target_of};
+use crate::libgit::{is_binary, Libgit};
+use crate::lsp::LspServer;
+use crate::serve::NoteServer;
+use crate::note::{Message, Note, Scope};
+use crate::path::PathResolver;
 
 pub mod argument;
 pub mod config;
@@ -25,40 +29,87 @@ where
     }
 
     pub fn add_note(&self, args: AddArgs) -> anyhow::Result<()> {
+        args.read_stdin()?;
         self.note_handler.add_note(&args)?;
         println!(
-            "Successfully added comment for `{}` in range `{}`",
+            "Successfully added comment for `{}` in {}",
             args.paths().relative().display(),
-            args.user_line()
+            target_of(&args)
         );
         Ok(())
     }
 
     pub fn read_note(&self, args: ReadArgs) -> anyhow::Result<()> {
+        args.read_stdin()?;
         let ledger = self.note_handler.read_note(&args)?;
-        let note = ledger.opaque_note();
+        let note = ledger.opaque_note()?;
         if args.formatted {
             let note_str = serde_json::to_string_pretty(&note)?;
             println!("{}", &note_str);
             return Ok(());
         }
+        note.wholes()
+            .iter()
+            .for_each(|found| self.pretty_print_row(Some(found), "*", ""));
+        if args.paths().canonical().is_dir() {
+            println!("{}", "(directory)".yellow());
+            return Ok(());
+        }
+        if is_binary(args.paths())? {
+            println!("{}", "(binary file)".yellow());
+            return Ok(());
+        }
         let content = ledger.content()?;
         self.pretty_print(&note, content)?;
         Ok(())
     }
 
+    pub fn blame_note(&self, args: BlameArgs) -> anyhow::Result<()> {
+        args.read_stdin()?;
+        if args.paths().canonical().is_dir() || is_binary(args.paths())? {
+            return Err(anyhow::anyhow!("{} has no line to blame", args.paths()));
+        }
+        let ledger = self.note_handler.read_note(&args)?;
+        let note = ledger.opaque_note()?;
+        note.wholes()
+            .iter()
+            .for_each(|found| self.pretty_print_row(Some(found), "*", ""));
+        let content = ledger.content()?;
+        let blamed = self.note_handler.blame(&args)?;
+        let mut changed = false;
+        for (line, row) in content.lines().enumerate() {
+            let message = note.find(line);
+            let (prefix, changed_after) = match blamed.get(line) {
+                Some(blame) => {
+                    let author: String = blame.author.chars().take(16).collect();
+                    let commit: String = blame.commit.chars().take(8).collect();
+                    let prefix = format!("{} {:<16} {}", commit, author, blame.time.format("%Y-%m-%d"));
+                    (prefix, message.is_some_and(|m| blame.changed_after(m)))
+                }
+                None => (format!("{:8} {:16} {:10}", "", "", ""), false),
+            };
+            changed |= changed_after;
+            print!("{} {} ", prefix.dimmed(), if changed_after { "!".yellow() } else { " ".normal() });
+            self.pretty_print_row(message, &(line + 1).to_string(), row); // starts from 1
+        }
+        if changed {
+            println!("{} the line has been committed after its note was written", "!".yellow());
+        }
+        Ok(())
+    }
+
     fn pretty_print(&self, note: &Note, content: String) -> anyhow::Result<()> {
         content.lines()
             .enumerate()
             .for_each(|(line, row)| {
                 let message = note.find(line);
-                self.pretty_print_row(message, line + 1, row) // starts from 1
+                self.pretty_print_row(message, &(line + 1).to_string(), row) // starts from 1
             });
         Ok(())
     }
 
-    fn pretty_print_row(&self, message: Option<&Message>, line: usize, row: &str) {
-        print!("{} ", line.to_string().yellow());
+    fn pretty_print_row(&self, message: Option<&Message>, line: &str, row: &str) {
+        print!("{} ", line.yellow());
         print!("{} ", row);
 
         match message {
@@ -84,15 +135,150 @@ where
             });
     }
 
+    pub fn list_notes(&self, args: ListArgs) -> anyhow::Result<()> {
+        let listing = self.note_handler.list_notes(&args)?;
+        // stderr, so that the output is still a valid json.
+        for (path, e) in &listing.skipped {
+            eprintln!("{} skipped {}: {}", "warning:".yellow(), path.display(), e);
+        }
+        let notes = listing.notes;
+        if args.formatted {
+            println!("{}", serde_json::to_string_pretty(&notes)?);
+            return Ok(());
+        }
+        for note in &notes {
+            println!("{}", note.reference.display().to_string().green());
+            for message in note.messages() {
+                let position = match message.scope {
+                    Scope::Line => (message.line + 1).to_string(), // starts from 1
+                    _ => "*".to_string(),
+                };
+                let first_line = message.message.lines().next().unwrap_or_default();
+                let tags = message.tags.iter().map(|tag| format!(" #{}", tag)).collect::<String>();
+                println!("  {} {}{}", position.yellow(), first_line, tags.cyan());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn export_notes(&self, args: ExportArgs) -> anyhow::Result<()> {
+        let (report, exported) = if args.review {
+            let comments = self.note_handler.review_comments(&args)?;
+            (serde_json::to_string_pretty(&comments)? + "\n", comments.len())
+        } else {
+            let excerpts = self.note_handler.export_notes(&args, args.context)?;
+            (render(args.format, &excerpts)?, excerpts.len())
+        };
+        match &args.output {
+            Some(output) => {
+                std::fs::write(output, report)?;
+                println!("Successfully exported {} note(s) into `{}`", exported, output.display());
+            }
+            None => print!("{}", report),
+        }
+        Ok(())
+    }
+
+    pub fn diff_notes(&self, args: DiffArgs) -> anyhow::Result<()> {
+        let diffs = self.note_handler.diff_notes(&args.path, args.range.as_deref(), args.context)?;
+        let mut invalidated = 0;
+        for (reference, hunks) in &diffs {
+            println!("{}", reference.display().to_string().green());
+            for hunk in hunks {
+                // in the header of unified diff, lines start from 1.
+                println!(
+                    "{}",
+                    format!("@@ -{},{} +{},{} @@", hunk.old.start + 1, hunk.old.len(), hunk.new.start + 1, hunk.new.len()).cyan()
+                );
+                for note in &hunk.notes {
+                    let first_line = note.message.message.lines().next().unwrap_or_default();
+                    let old_line = (note.message.line + 1).to_string(); // starts from 1
+                    match note.new_line {
+                        Some(new_line) => println!("  {} -> {} {}", old_line.yellow(), (new_line + 1).to_string().yellow(), first_line),
+                        None => {
+                            invalidated += 1;
+                            println!("  {} -> {} {} {}", old_line.yellow(), "x".red(), first_line, "(invalidated)".red());
+                        }
+                    }
+                }
+            }
+        }
+        if invalidated > 0 {
+            println!("{} note(s) will no longer be anchored after the change", invalidated);
+        }
+        Ok(())
+    }
+
+    pub fn import_comments(&self, args: ImportCommentsArgs) -> anyhow::Result<()> {
+        let mut imported = 0;
+        for paths in &args.path {
+            imported += self.note_handler.import_comments(paths, &args.marker, args.strip)?;
+        }
+        println!("Successfully imported {} comment(s) as notes", imported);
+        Ok(())
+    }
+
+    pub fn inline_notes(&self, args: InlineArgs) -> anyhow::Result<()> {
+        let patch = self.note_handler.inline_notes(&args, &args.marker)?;
+        match &args.output {
+            Some(output) => {
+                std::fs::write(output, patch)?;
+                println!("Successfully wrote a patch into `{}`. apply it with `git apply`", output.display());
+            }
+            None => print!("{}", patch),
+        }
+        Ok(())
+    }
+
+    pub fn export_git_notes(&self, args: GitNotesExportArgs) -> anyhow::Result<()> {
+        let paths = PathResolver::resolve(std::env::current_dir()?, ".")?;
+        let exported = self.note_handler.export_git_notes(&paths, &args.rev, &args.notes_ref)?;
+        println!("Successfully exported {} note(s) into `refs/notes/{}` of `{}`", exported, args.notes_ref, args.rev);
+        Ok(())
+    }
+
+    pub fn import_git_notes(&self, args: GitNotesImportArgs) -> anyhow::Result<()> {
+        let paths = PathResolver::resolve(std::env::current_dir()?, ".")?;
+        let imported = self.note_handler.import_git_notes(&paths, &args.notes_ref)?;
+        println!("Successfully imported {} note(s) from `refs/notes/{}`", imported, args.notes_ref);
+        Ok(())
+    }
+
+    pub fn lsp(self, _args: LspArgs) -> anyhow::Result<()> {
+        let server = LspServer::new(self.note_handler);
+        server.run(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+    }
+
+    pub fn serve(self, _args: ServeArgs) -> anyhow::Result<()> {
+        let server = NoteServer::new(self.note_handler, std::env::current_dir()?);
+        server.run(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+    }
+
+    pub fn migrate(&self, _args: MigrateArgs) -> anyhow::Result<()> {
+        let paths = PathResolver::resolve(std::env::current_dir()?, ".")?;
+        let migrated = self.note_handler.migrate_notes(&paths)?;
+        println!("Successfully migrated {} note(s) to the latest format", migrated);
+        Ok(())
+    }
+
+    pub fn convert(&self, args: ConvertArgs) -> anyhow::Result<()> {
+        let paths = PathResolver::resolve(std::env::current_dir()?, ".")?;
+        let converted = self.note_handler.convert_notes(&paths, args.to)?;
+        println!("Successfully moved {} note(s) into `{}` storage", converted, args.to);
+        Ok(())
+    }
+
     pub fn edit_note(&self, args: EditArgs) -> anyhow::Result<()> {
+        args.read_stdin()?;
         self.note_handler.edit_note(&args)?;
-        println!("Successfully edited comment for `{}` in range `{}`", &args.paths, args.line);
+        println!("Successfully edited comment for `{}` in {}", &args.paths, target_of(&args));
         Ok(())
     }
 
     pub fn delete_note(&self, args: DeleteArgs) -> anyhow::Result<()> {
+        args.read_stdin()?;
         self.note_handler.delete_note(&args)?;
-        println!("Successfully deleted comment for `{}` in range `{}`", &args.paths, args.user_line());
+        println!("Successfully deleted comment for `{}` in {}", &args.paths, target_of(&args));
         Ok(())
     }
 }
@@ -0,0 +1,295 @@
+use std::path::Path;
+
+use regex::Regex;
+
+/// How comments are written in a language, which is told by extension of file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommentSyntax {
+    pub start: &'static str,
+    /// closes a block comment, which is none for a line comment.
+    pub end: Option<&'static str>,
+}
+
+impl CommentSyntax {
+    const fn line(start: &'static str) -> Self {
+        CommentSyntax { start, end: None }
+    }
+
+    const fn block(start: &'static str, end: &'static str) -> Self {
+        CommentSyntax { start, end: Some(end) }
+    }
+
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        let syntax = match extension.as_str() {
+            "rs" | "java" | "kt" | "kts" | "scala" | "groovy" | "gradle" | "js" | "jsx" | "mjs" | "ts" | "tsx"
+            | "go" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "swift" | "dart" | "php" | "proto" => Self::line("//"),
+            "py" | "rb" | "sh" | "bash" | "zsh" | "pl" | "r" | "yml" | "yaml" | "toml" | "ex" | "exs" | "tf" => Self::line("#"),
+            "sql" | "lua" | "hs" => Self::line("--"),
+            "el" | "clj" | "lisp" => Self::line(";;"),
+            "css" | "scss" => Self::block("/*", "*/"),
+            "html" | "htm" | "xml" | "vue" | "md" => Self::block("<!--", "-->"),
+            _ => return None,
+        };
+        Some(syntax)
+    }
+
+    /// Writes `text` as a comment indented with `indent`.
+    fn comment(&self, indent: &str, text: &str) -> String {
+        match self.end {
+            Some(end) => format!("{}{} {} {}", indent, self.start, text, end),
+            None => format!("{}{} {}", indent, self.start, text),
+        }
+    }
+}
+
+/// A marker comment found in source, e.g. `// NOTE(jane): explains why` which says `explains why` by `jane`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkerComment {
+    /// the line the marker describes, which starts from 0.
+    /// It is the line of marker itself if it trails code, or the next line of code if it stands alone.
+    pub line: usize,
+    /// the same line as [`MarkerComment::line`], in the content without markers.
+    pub stripped_line: usize,
+    pub author: Option<String>,
+    pub message: String,
+}
+
+/// Marker comments in content, along with the content without them.
+#[derive(Debug, Clone)]
+pub struct Harvest {
+    pub markers: Vec<MarkerComment>,
+    pub stripped: String,
+}
+
+/// Finds comments starting with `marker`, e.g. `NOTE`, which are followed by an optional author in parentheses and a colon.
+/// A marker standing alone at the end of content describes no line, so that it is left as it is.
+/// So is a marker in a string literal, which is told by quotes left open before it.
+/// Line endings are kept as they are, either `\n` or `\r\n`.
+pub fn harvest(content: &str, syntax: CommentSyntax, marker: &str) -> Harvest {
+    return harvest_where(content, syntax, marker, |_| true);
+}
+
+/// Same as [`harvest`], but only markers which `take` accepts are harvested and stripped, and the others are left as they are.
+/// `take` is called in order of markers, with [`MarkerComment::stripped_line`] not settled yet.
+pub fn harvest_where<F>(content: &str, syntax: CommentSyntax, marker: &str, mut take: F) -> Harvest
+where
+    F: FnMut(&MarkerComment) -> bool,
+{
+    let pattern = Regex::new(&format!(
+        r"^{}\s*{}(?:\((?P<author>[^)]*)\))?:\s*(?P<message>.*?)\s*{}$",
+        regex::escape(syntax.start),
+        regex::escape(marker),
+        syntax.end.map(|end| format!("(?:{})?", regex::escape(end))).unwrap_or_default(),
+    )).expect("marker pattern is always valid");
+
+    let lines: Vec<(&str, &str)> = content.split_inclusive('\n').map(split_ending).collect();
+    let mut markers = Vec::new();
+    let mut stripped: Vec<String> = Vec::new();
+    // standalone markers waiting for the next line of code.
+    let mut pending: Vec<(usize, Option<String>, String)> = Vec::new();
+    // settles pending markers on the line of code at `index`, keeping the ones not taken above it.
+    let settle = |take: &mut F, pending: &mut Vec<(usize, Option<String>, String)>, index: usize, stripped: &mut Vec<String>, markers: &mut Vec<MarkerComment>| {
+        let mut taken = Vec::new();
+        for (at, author, message) in pending.drain(..) {
+            let found = MarkerComment { line: index, stripped_line: 0, author, message };
+            if take(&found) {
+                taken.push(found);
+            } else {
+                stripped.push(format!("{}{}", lines[at].0, lines[at].1));
+            }
+        }
+        markers.extend(taken.into_iter().map(|found| MarkerComment { stripped_line: stripped.len(), ..found }));
+    };
+
+    for (index, (line, ending)) in lines.iter().enumerate() {
+        let found = line.match_indices(syntax.start)
+            .filter(|(start, _)| !in_string(&line[..*start]))
+            .find_map(|(start, _)| pattern.captures(&line[start..]).map(|captures| (&line[..start], captures)));
+        let Some((code, captures)) = found else {
+            settle(&mut take, &mut pending, index, &mut stripped, &mut markers);
+            stripped.push(format!("{}{}", line, ending));
+            continue;
+        };
+        let author = captures.name("author").map(|a| a.as_str().trim().to_string()).filter(|a| !a.is_empty());
+        let message = captures["message"].to_string();
+        if code.trim().is_empty() {
+            pending.push((index, author, message));
+            continue;
+        }
+        settle(&mut take, &mut pending, index, &mut stripped, &mut markers);
+        let trailing = MarkerComment { line: index, stripped_line: stripped.len(), author, message };
+        if take(&trailing) {
+            markers.push(trailing);
+            stripped.push(format!("{}{}", code.trim_end(), ending));
+        } else {
+            stripped.push(format!("{}{}", line, ending));
+        }
+    }
+    // nothing follows, so they are kept.
+    stripped.extend(pending.iter().map(|(index, _, _)| format!("{}{}", lines[*index].0, lines[*index].1)));
+
+    return Harvest { markers, stripped: stripped.concat() };
+}
+
+/// Splits a line into its text and its line ending, which is empty for the last line without one.
+fn split_ending(line: &str) -> (&str, &str) {
+    let text = line.trim_end_matches(['\r', '\n']);
+    (text, &line[text.len()..])
+}
+
+/// Tells whether `code` leaves a string literal open, which is quoted by any of `"`, `'` and `` ` ``.
+fn in_string(code: &str) -> bool {
+    let mut open: Option<char> = None;
+    let mut escaped = false;
+    for c in code.chars() {
+        match (open, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (None, '"' | '\'' | '`') => open = Some(c),
+            (Some(quote), c) if c == quote => open = None,
+            _ => {}
+        }
+    }
+    open.is_some()
+}
+
+/// A message to write as a comment above its line, which starts from 0.
+#[derive(Debug, Clone)]
+pub struct InlineComment {
+    pub line: usize,
+    pub author: Option<String>,
+    pub message: String,
+}
+
+/// Writes every comment above its line, indented as the line is, as a marker comment which [`harvest`] reads back.
+/// A message of several lines is written as a comment per line.
+pub fn inline(content: &str, syntax: CommentSyntax, marker: &str, comments: &[InlineComment]) -> String {
+    let mut out = String::new();
+    for (index, (line, ending)) in content.split_inclusive('\n').map(split_ending).enumerate() {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        // comments end as the line does, or with `\n` if it is the last line without one.
+        let comment_ending = if ending.is_empty() { "\n" } else { ending };
+        for comment in comments.iter().filter(|c| c.line == index) {
+            let head = match &comment.author {
+                Some(author) => format!("{}({}):", marker, author),
+                None => format!("{}:", marker),
+            };
+            let mut message_lines = comment.message.lines();
+            out += &syntax.comment(indent, &format!("{} {}", head, message_lines.next().unwrap_or_default()));
+            out += comment_ending;
+            for rest in message_lines {
+                out += &syntax.comment(indent, rest);
+                out += comment_ending;
+            }
+        }
+        out += line;
+        out += ending;
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::comment::{harvest, harvest_where, inline, CommentSyntax, InlineComment};
+
+    #[test]
+    fn syntax_by_extension() -> anyhow::Result<()> {
+        assert_eq!(CommentSyntax::of(Path::new("src/main.rs")).unwrap().start, "//");
+        assert_eq!(CommentSyntax::of(Path::new("setup.py")).unwrap().start, "#");
+        assert_eq!(CommentSyntax::of(Path::new("index.html")).unwrap().end, Some("-->"));
+        assert_eq!(CommentSyntax::of(Path::new("LICENSE")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn harvest_markers() -> anyhow::Result<()> {
+        // given
+        let content = "fn main() {\n    // NOTE(jane): explains why\n    let a = 1; // NOTE: trailing\n    // not a marker\n    let url = \"http://x\";\n    // NOTE: dangling\n";
+        let syntax = CommentSyntax::of(Path::new("main.rs")).unwrap();
+
+        // when
+        let harvest = harvest(content, syntax, "NOTE");
+
+        // then
+        assert_eq!(harvest.markers.len(), 2);
+        assert_eq!((harvest.markers[0].line, harvest.markers[0].stripped_line), (2, 1));
+        assert_eq!(harvest.markers[0].author, Some("jane".to_string()));
+        assert_eq!(harvest.markers[0].message, "explains why");
+        assert_eq!((harvest.markers[1].line, harvest.markers[1].stripped_line), (2, 1));
+        assert_eq!(harvest.markers[1].message, "trailing");
+        assert_eq!(harvest.stripped, "fn main() {\n    let a = 1;\n    // not a marker\n    let url = \"http://x\";\n    // NOTE: dangling\n");
+        Ok(())
+    }
+
+    #[test]
+    fn harvest_skips_string_literals() -> anyhow::Result<()> {
+        // given
+        let content = "let s = \"// NOTE: not a marker\";\nlet t = \"a \\\" b\"; // NOTE: after a string\nprint('# NOTE: quoted')\n";
+        let syntax = CommentSyntax::of(Path::new("main.rs")).unwrap();
+
+        // when
+        let rust = harvest(content, syntax, "NOTE");
+
+        // then
+        assert_eq!(rust.markers.len(), 1);
+        assert_eq!(rust.markers[0].line, 1);
+        assert_eq!(rust.markers[0].message, "after a string");
+        assert_eq!(rust.stripped, "let s = \"// NOTE: not a marker\";\nlet t = \"a \\\" b\";\nprint('# NOTE: quoted')\n");
+        let python = harvest(content, CommentSyntax::of(Path::new("main.py")).unwrap(), "NOTE");
+        assert_eq!(python.markers.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn harvest_only_taken() -> anyhow::Result<()> {
+        // given
+        let content = "a\n// NOTE: kept\n// NOTE: taken\nb // NOTE: kept too\nc // NOTE: on c\n";
+        let syntax = CommentSyntax::of(Path::new("main.rs")).unwrap();
+
+        // when
+        let harvest = harvest_where(content, syntax, "NOTE", |found| found.message.starts_with("taken") || found.line == 4);
+
+        // then
+        assert_eq!(harvest.stripped, "a\n// NOTE: kept\nb // NOTE: kept too\nc\n");
+        assert_eq!(harvest.markers.iter().map(|m| (m.line, m.stripped_line)).collect::<Vec<_>>(), vec![(3, 2), (4, 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_line_endings() -> anyhow::Result<()> {
+        // given
+        let content = "a\r\n  // NOTE: on b\r\n  b\r\nc // NOTE: on c";
+        let syntax = CommentSyntax::of(Path::new("main.rs")).unwrap();
+
+        // when
+        let harvest = harvest(content, syntax, "NOTE");
+        let comments = vec![InlineComment { line: 1, author: None, message: "on b".to_string() }];
+
+        // then
+        assert_eq!(harvest.stripped, "a\r\n  b\r\nc");
+        assert_eq!(inline(&harvest.stripped, syntax, "NOTE", &comments), "a\r\n  // NOTE: on b\r\n  b\r\nc");
+        Ok(())
+    }
+
+    #[test]
+    fn inline_round_trip() -> anyhow::Result<()> {
+        // given
+        let content = "<ul>\n  <li>a</li>\n</ul>\n";
+        let syntax = CommentSyntax::of(Path::new("index.html")).unwrap();
+        let comments = vec![InlineComment { line: 1, author: Some("jane".to_string()), message: "first".to_string() }];
+
+        // when
+        let inlined = inline(content, syntax, "NOTE", &comments);
+
+        // then
+        assert_eq!(inlined, "<ul>\n  <!-- NOTE(jane): first -->\n  <li>a</li>\n</ul>\n");
+        let harvest = harvest(&inlined, syntax, "NOTE");
+        assert_eq!(harvest.stripped, content);
+        assert_eq!(harvest.markers[0].stripped_line, 1);
+        assert_eq!(harvest.markers[0].message, "first");
+        Ok(())
+    }
+}
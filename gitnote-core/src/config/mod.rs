@@ -1,6 +1,7 @@
 use std::{env, fs};
 use std::fmt::{Display, Formatter};
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::Context;
 use encoding_rs::Encoding;
@@ -8,7 +9,8 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
 use crate::config::PersistenceType::Ephemeral;
-use crate::path::PathResolver;
+use crate::path::{PathResolver, Paths};
+use crate::utils::write_atomically;
 
 pub mod options;
 
@@ -26,6 +28,10 @@ pub struct Config {
     persistence_type: PersistenceType,
     #[serde(default)]
     charset: Charset,
+    #[serde(default)]
+    libgit: LibgitBackend,
+    #[serde(default)]
+    storage: Storage,
 }
 
 impl Default for Config {
@@ -33,6 +39,8 @@ impl Default for Config {
         Config {
             persistence_type: PersistenceType::default(),
             charset: Charset::default(),
+            libgit: LibgitBackend::default(),
+            storage: Storage::default(),
         }
     }
 }
@@ -42,6 +50,10 @@ impl Config {
     where
         P: AsRef<Path>,
     {
+        // a repository has no config until the first note is written.
+        if !p.as_ref().exists() {
+            return Ok(Config::default());
+        }
         let s = fs::read_to_string(p)?;
         if s.is_empty() {
             return Ok(Config::default());
@@ -49,6 +61,11 @@ impl Config {
         Self::resolve_from_str(s.as_str())
     }
 
+    /// Resolves the config of the repository which given paths belongs to.
+    pub fn load(paths: &Paths) -> anyhow::Result<Config> {
+        Self::resolve(paths.config())
+    }
+
     fn resolve_from_str(s: &str) -> anyhow::Result<Config> {
         serde_yaml_ng::from_str::<Self>(s)
             .context("Failed to parse config")
@@ -57,6 +74,24 @@ impl Config {
     pub fn charset(&self) -> &Charset {
         &self.charset
     }
+
+    pub fn libgit(&self) -> LibgitBackend {
+        self.libgit
+    }
+
+    pub fn storage(&self) -> Storage {
+        self.storage
+    }
+
+    pub fn with_storage(self, storage: Storage) -> Self {
+        Config { storage, ..self }
+    }
+
+    /// Writes the config of the repository which given paths belongs to.
+    pub fn save(&self, paths: &Paths) -> anyhow::Result<()> {
+        let content = serde_yaml_ng::to_string(self)?;
+        write_atomically(&paths.config(), content.as_bytes())
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -73,12 +108,78 @@ impl Default for PersistenceType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Which [`crate::libgit::Libgit`] implementation talks to the git repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LibgitBackend {
+    /// reads and writes git objects by itself, without spawning `git`.
+    #[default]
+    Manual,
+    /// spawns `git` process for every operation.
+    Process,
+}
+
+impl FromStr for LibgitBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "manual" => Ok(LibgitBackend::Manual),
+            "process" => Ok(LibgitBackend::Process),
+            _ => Err(anyhow::anyhow!("Invalid libgit backend : `{}`. expected `manual` or `process`", s)),
+        }
+    }
+}
+
+/// How notes are laid out in `.git/notes`. `git note convert` moves notes from one to another.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// a json file per annotated path, fanned out by the hash of the path.
+    #[default]
+    Files,
+    /// a single append-only log of notes, which is read at once. Suits repositories with a lot of notes.
+    Log,
+    /// a pretty yaml file per annotated path, which mirrors the source tree, e.g. `.git/notes/tree/src/main.rs.yml`.
+    /// Suits reading notes by hand, and merging them.
+    Tree,
+}
+
+impl FromStr for Storage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "files" => Ok(Storage::Files),
+            "log" => Ok(Storage::Log),
+            "tree" => Ok(Storage::Tree),
+            _ => Err(anyhow::anyhow!("Invalid storage : `{}`. expected `files`, `log` or `tree`", s)),
+        }
+    }
+}
+
+impl Display for Storage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Storage::Files => write!(f, "files"),
+            Storage::Log => write!(f, "log"),
+            Storage::Tree => write!(f, "tree"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Charset {
     encoding: &'static Encoding,
 }
 
 impl Charset {
+    pub fn for_label(label: &str) -> anyhow::Result<Self> {
+        Encoding::for_label(label.as_bytes())
+            .map(|encoding| Charset { encoding })
+            .ok_or(anyhow::anyhow!("`{}` is Unknown charset", label))
+    }
+
     pub fn decode(&self, bytes: &[u8]) -> anyhow::Result<String> {
         let (decoded, _, error) = self.encoding.decode(bytes);
         match error {
@@ -86,6 +187,14 @@ impl Charset {
             true => Err(anyhow::anyhow!("Failed to decode with charset {}", self.encoding.name())),
         }
     }
+
+    pub fn encode(&self, text: &str) -> anyhow::Result<Vec<u8>> {
+        let (encoded, _, error) = self.encoding.encode(text);
+        match error {
+            false => Ok(encoded.to_vec()),
+            true => Err(anyhow::anyhow!("Failed to encode with charset {}", self.encoding.name())),
+        }
+    }
 }
 
 impl Display for Charset {
@@ -139,6 +248,18 @@ charset: utf-8
         let config = Config::resolve_from_str(text).unwrap();
         assert_eq!(config.persistence_type, Ephemeral);
         assert_eq!(config.charset.encoding.name(), "UTF-8");
+        assert_eq!(config.libgit, LibgitBackend::Manual);
+    }
+
+    #[test]
+    fn libgit() {
+        let text = r#"
+libgit: process
+        "#;
+        let config = Config::resolve_from_str(text).unwrap();
+        assert_eq!(config.libgit, LibgitBackend::Process);
+        assert_eq!("Manual".parse::<LibgitBackend>().unwrap(), LibgitBackend::Manual);
+        assert!("unknown".parse::<LibgitBackend>().is_err());
     }
 
     #[test]
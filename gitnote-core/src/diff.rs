@@ -1,6 +1,8 @@
+use std::ops::Range;
+
 use similar::{ChangeTag, TextDiff};
 
-use crate::note::Message;
+use crate::note::{Message, Scope};
 
 #[derive(Debug)]
 pub struct DiffModel {
@@ -66,10 +68,72 @@ impl Differ for SimilarDiffer {
     }
 }
 
+/// A hunk of change, along with messages anchored in or near its lines.
+#[derive(Debug, Clone)]
+pub struct NotedHunk {
+    /// lines of the old content which the hunk covers including its context, which start from 0.
+    pub old: Range<usize>,
+    /// lines of the new content which the hunk covers including its context, which start from 0.
+    pub new: Range<usize>,
+    pub notes: Vec<HunkNote>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HunkNote {
+    /// anchored to the old content.
+    pub message: Message,
+    /// where the message is anchored to after the change, which is none if the change invalidates it.
+    pub new_line: Option<usize>,
+}
+
+/// Splits the change from `old` to `new` into hunks with `radius` lines of context, just as `git diff` does,
+/// and predicts where each message in or near the hunks is anchored to after the change.
+/// `messages` have to be anchored to `old`, and messages far from any change are left out.
+pub fn noted_hunks<D>(differ: &D, old: &String, new: &String, messages: &[Message], radius: usize) -> Vec<NotedHunk>
+where
+    D: Differ + ?Sized,
+{
+    return TextDiff::from_lines(old, new)
+        .grouped_ops(radius)
+        .iter()
+        .filter_map(|group| {
+            let (first, last) = (group.first()?, group.last()?);
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let notes = messages.iter()
+                .filter(|m| m.scope == Scope::Line && old_range.contains(&m.line))
+                .map(|m| HunkNote { message: m.clone(), new_line: m.reanchor(differ, old, new) })
+                .collect();
+            Some(NotedHunk { old: old_range, new: new_range, notes })
+        })
+        .collect();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_noted_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n".to_string();
+        let new = "a\nX\nb\nc\nd\ne\nf\ng\nh\nY\nj\n".to_string();
+        let noted = |line: usize, snippet: &str| Message::on_line(line, snippet, snippet);
+        let messages = vec![noted(2, "c"), noted(5, "f"), noted(8, "i")];
+
+        let hunks = noted_hunks(&SimilarDiffer, &old, &new, &messages, 1);
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old.clone(), hunks[0].new.clone()), (0..2, 0..3));
+        // `c` is not in the first hunk, nor `f` in any.
+        assert!(hunks[0].notes.is_empty());
+        assert_eq!(hunks[1].notes.len(), 1);
+        assert_eq!(hunks[1].notes[0].message.message, "i");
+        assert_eq!(hunks[1].notes[0].new_line, None);
+
+        let hunks = noted_hunks(&SimilarDiffer, &old, &new, &messages, 3);
+        assert_eq!(hunks[0].notes[0].new_line, Some(3));
+    }
+
     #[test]
     fn test_diff_same() {
         let old = r#"
@@ -0,0 +1,45 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// Errors worth telling apart from the others, e.g. to show a dedicated message in IDE.
+/// They are returned wrapped in [`anyhow::Error`], so use `downcast_ref` to find them.
+#[derive(Debug)]
+pub enum NoteError {
+    /// A line note is requested on a file which is not a text.
+    Binary(PathBuf),
+    /// A note file is written by a newer git-note, of which format this one doesn't know.
+    UnsupportedVersion(u32),
+    /// A note file can't be parsed, e.g. it is truncated by a crash of an older git-note which didn't write atomically.
+    Corrupt(PathBuf),
+    /// Another writer holds the lock of a note file, and doesn't release it in time.
+    Busy(PathBuf),
+}
+
+impl Display for NoteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoteError::Binary(path) => write!(
+                f,
+                "`{}` looks like a binary file, so a note cannot be anchored to its line. add a note to the whole file instead.",
+                path.display()
+            ),
+            NoteError::UnsupportedVersion(version) => write!(
+                f,
+                "note file of version {} is written by a newer git-note, which is not supported. upgrade git-note to read it.",
+                version
+            ),
+            NoteError::Busy(lock) => write!(
+                f,
+                "note store is busy, as `{}` is held by another git-note. try again, or remove it if no other git-note is running.",
+                lock.display()
+            ),
+            NoteError::Corrupt(path) => write!(
+                f,
+                "note file `{}` is corrupt. restore it from a backup, or remove it to drop its notes.",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
@@ -0,0 +1,329 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::SecondsFormat;
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::note::{Message, Scope};
+
+/// How [`Excerpt`]s are rendered into a report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    /// a single page which embeds its own style, so that it is opened without any other file.
+    Html,
+    /// a row per message, to be opened in a spreadsheet.
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" => Ok(ExportFormat::Html),
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid format : `{}`. expected `md`, `html`, `csv` or `json`", s)),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Markdown => write!(f, "md"),
+            ExportFormat::Html => write!(f, "html"),
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// A message to report, along with the code around its line as it is now.
+#[derive(Debug, Clone, Serialize)]
+pub struct Excerpt {
+    pub reference: PathBuf,
+    pub scope: Scope,
+    /// starts from 1, which is none for a message on the whole file or directory.
+    pub line: Option<usize>,
+    pub context: Vec<ContextLine>,
+    pub message: String,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    /// in RFC 3339, e.g. `2024-05-01T09:00:00Z`.
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContextLine {
+    /// starts from 1.
+    pub line: usize,
+    pub code: String,
+    /// whether the message is anchored to this line.
+    pub anchored: bool,
+}
+
+impl Excerpt {
+    /// `content` is the current content of the file, which `radius` lines around the anchored line are taken from.
+    pub fn of(reference: &Path, message: &Message, content: Option<&str>, radius: usize) -> Self {
+        let line = (message.scope == Scope::Line).then_some(message.line);
+        let context = match (line, content) {
+            (Some(line), Some(content)) => content.lines()
+                .enumerate()
+                .skip(line.saturating_sub(radius))
+                .take(line.min(radius) + radius + 1)
+                .map(|(index, code)| ContextLine { line: index + 1, code: code.to_string(), anchored: index == line })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Excerpt {
+            reference: reference.to_path_buf(),
+            scope: message.scope,
+            line: line.map(|line| line + 1), // starts from 1
+            context,
+            message: message.message.clone(),
+            author: message.author.clone(),
+            tags: message.tags.clone(),
+            created_at: message.created_at().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    fn position(&self) -> String {
+        match (self.scope, self.line) {
+            (Scope::Line, Some(line)) => format!("line {}", line),
+            (Scope::Directory, _) => "the directory".to_string(),
+            _ => "the file".to_string(),
+        }
+    }
+}
+
+/// A comment on a line of pull request, in the shape which review APIs of GitHub and GitLab take.
+/// Uploading them is up to the caller, e.g. `gh api repos/{owner}/{repo}/pulls/{number}/comments --input`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReviewComment {
+    pub path: String,
+    /// the line in the file at [`ReviewComment::commit_id`], which starts from 1.
+    pub line: usize,
+    /// `RIGHT`, which is the side of the commit, as notes are on the latest content.
+    pub side: String,
+    pub commit_id: String,
+    pub body: String,
+}
+
+impl ReviewComment {
+    /// `message` has to be anchored to the file at `commit_id`.
+    pub fn of(reference: &Path, message: &Message, commit_id: &str) -> Self {
+        ReviewComment {
+            path: reference.display().to_string().replace('\\', "/"),
+            line: message.line + 1, // starts from 1
+            side: "RIGHT".to_string(),
+            commit_id: commit_id.to_string(),
+            body: message.message.clone(),
+        }
+    }
+}
+
+pub fn render(format: ExportFormat, excerpts: &[Excerpt]) -> anyhow::Result<String> {
+    let rendered = match format {
+        ExportFormat::Markdown => markdown(excerpts),
+        ExportFormat::Html => html(excerpts),
+        ExportFormat::Csv => csv(excerpts),
+        ExportFormat::Json => serde_json::to_string_pretty(excerpts)? + "\n",
+    };
+    return Ok(rendered);
+}
+
+fn markdown(excerpts: &[Excerpt]) -> String {
+    let mut out = String::from("# Notes\n");
+    for (reference, group) in &excerpts.iter().chunk_by(|excerpt| &excerpt.reference) {
+        out += &format!("\n## `{}`\n", reference.display());
+        for excerpt in group {
+            out += &format!("\n### {}\n\n", excerpt.position());
+            if !excerpt.context.is_empty() {
+                let code = numbered(&excerpt.context);
+                let fence = "`".repeat(longest_backticks(&code).max(2) + 1);
+                out += &format!("{}\n{}{}\n\n", fence, code, fence);
+            }
+            for line in excerpt.message.lines() {
+                out += &format!("> {}\n", line).replace("> \n", ">\n");
+            }
+            out += &format!("\n{}\n", signature(excerpt, |tag| format!("`#{}`", tag)));
+        }
+    }
+    return out;
+}
+
+fn html(excerpts: &[Excerpt]) -> String {
+    let mut out = String::from(HTML_HEAD);
+    for (reference, group) in &excerpts.iter().chunk_by(|excerpt| &excerpt.reference) {
+        out += &format!("<section>\n<h2>{}</h2>\n", escape_html(&reference.display().to_string()));
+        for excerpt in group {
+            out += &format!("<article>\n<h3>{}</h3>\n", excerpt.position());
+            if !excerpt.context.is_empty() {
+                out += "<pre>";
+                for line in &excerpt.context {
+                    let class = if line.anchored { " class=\"anchored\"" } else { "" };
+                    out += &format!("<span{}><i>{}</i>{}</span>\n", class, line.line, escape_html(&line.code));
+                }
+                out += "</pre>\n";
+            }
+            out += &format!("<blockquote>{}</blockquote>\n", escape_html(&excerpt.message).replace('\n', "<br>\n"));
+            out += &format!("<p>{}</p>\n</article>\n", escape_html(&signature(excerpt, |tag| format!("#{}", tag))));
+        }
+        out += "</section>\n";
+    }
+    out += "</body>\n</html>\n";
+    return out;
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Notes</title>
+<style>
+body { font-family: sans-serif; max-width: 960px; margin: 2em auto; color: #24292f; }
+h2 { font-family: monospace; border-bottom: 1px solid #d0d7de; }
+pre { background: #f6f8fa; padding: 0.5em; overflow-x: auto; }
+pre span { display: block; }
+pre i { display: inline-block; width: 3em; color: #8c959f; font-style: normal; user-select: none; }
+pre .anchored { background: #fff8c5; }
+blockquote { margin: 0; padding-left: 1em; border-left: 4px solid #54aeff; }
+p { color: #57606a; font-size: 0.9em; }
+</style>
+</head>
+<body>
+<h1>Notes</h1>
+"#;
+
+fn csv(excerpts: &[Excerpt]) -> String {
+    let mut out = String::from("path,line,scope,author,tags,created_at,message,context\n");
+    for excerpt in excerpts {
+        let row = [
+            excerpt.reference.display().to_string(),
+            excerpt.line.map(|line| line.to_string()).unwrap_or_default(),
+            format!("{:?}", excerpt.scope).to_lowercase(),
+            excerpt.author.clone().unwrap_or_default(),
+            excerpt.tags.join(" "),
+            excerpt.created_at.clone(),
+            excerpt.message.clone(),
+            numbered(&excerpt.context),
+        ];
+        out += &row.iter().map(|field| escape_csv(field)).join(",");
+        out += "\n";
+    }
+    return out;
+}
+
+/// Code with line numbers, marking the anchored line with `>`.
+fn numbered(context: &[ContextLine]) -> String {
+    let width = context.last().map(|line| line.line.to_string().len()).unwrap_or_default();
+    return context.iter()
+        .map(|line| format!("{} {:>width$} | {}\n", if line.anchored { ">" } else { " " }, line.line, line.code, width = width))
+        .collect();
+}
+
+/// e.g. `Jane Doe <jane@example.com>, 2024-05-01T09:00:00Z #todo`
+fn signature<F>(excerpt: &Excerpt, tag: F) -> String
+where
+    F: Fn(&String) -> String,
+{
+    let mut signature = match &excerpt.author {
+        Some(author) => format!("{}, {}", author, excerpt.created_at),
+        None => excerpt.created_at.clone(),
+    };
+    for t in &excerpt.tags {
+        signature += &format!(" {}", tag(t));
+    }
+    return signature;
+}
+
+/// A code fence has to be longer than any run of backticks in the code.
+fn longest_backticks(code: &str) -> usize {
+    return code.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+}
+
+fn escape_html(s: &str) -> String {
+    return s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+fn escape_csv(s: &str) -> String {
+    if !s.contains([',', '"', '\n', '\r']) {
+        return s.to_string();
+    }
+    return format!("\"{}\"", s.replace('"', "\"\""));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::export::{render, Excerpt, ExportFormat};
+    use crate::note::{Message, Scope};
+
+    fn excerpts() -> Vec<Excerpt> {
+        let content = "fn main() {\n    let a = \"<b>\";\n    println!(\"{}\", a);\n}";
+        let line = Message::on_line(1, "", "escapes, \"quotes\"\nand <tags>")
+            .signed(Some("Jane Doe <jane@example.com>".to_string()), vec!["todo".to_string()]);
+        let whole = Message::whole(Scope::File, "entry point".to_string());
+        let reference = PathBuf::from("src/main.rs");
+        vec![
+            Excerpt::of(&reference, &whole, Some(content), 1),
+            Excerpt::of(&reference, &line, Some(content), 1),
+        ]
+    }
+
+    #[test]
+    fn context() -> anyhow::Result<()> {
+        // given
+        let excerpts = excerpts();
+
+        // then
+        assert!(excerpts[0].context.is_empty());
+        assert_eq!(excerpts[1].line, Some(2));
+        assert_eq!(excerpts[1].context.iter().map(|line| line.line).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(excerpts[1].context[1].anchored);
+        Ok(())
+    }
+
+    #[test]
+    fn every_format() -> anyhow::Result<()> {
+        // given
+        let excerpts = excerpts();
+
+        // when
+        let markdown = render(ExportFormat::Markdown, &excerpts)?;
+        let html = render(ExportFormat::Html, &excerpts)?;
+        let csv = render(ExportFormat::Csv, &excerpts)?;
+        let json = render(ExportFormat::Json, &excerpts)?;
+
+        // then
+        assert!(markdown.contains("## `src/main.rs`\n\n### the file\n\n> entry point\n"), "{}", markdown);
+        assert!(markdown.contains("```\n  1 | fn main() {\n> 2 |     let a = \"<b>\";\n"), "{}", markdown);
+        assert!(markdown.contains("> escapes, \"quotes\"\n> and <tags>\n"), "{}", markdown);
+        assert!(markdown.contains("Jane Doe <jane@example.com>, "), "{}", markdown);
+
+        assert!(html.contains("<style>"));
+        assert!(!html.contains("<link") && !html.contains("<script"));
+        assert!(html.contains("<span class=\"anchored\"><i>2</i>    let a = &quot;&lt;b&gt;&quot;;</span>"), "{}", html);
+        assert!(html.contains("and &lt;tags&gt;"), "{}", html);
+
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "path,line,scope,author,tags,created_at,message,context");
+        assert!(rows[1].starts_with("src/main.rs,,file,,,"), "{}", csv);
+        assert!(csv.contains(",line,Jane Doe <jane@example.com>,todo,"), "{}", csv);
+        assert!(csv.contains(",\"escapes, \"\"quotes\"\"\nand <tags>\","), "{}", csv);
+
+        let parsed: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(parsed[1]["context"][1]["code"], "    let a = \"<b>\";");
+        Ok(())
+    }
+}
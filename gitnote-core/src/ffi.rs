@@ -0,0 +1,214 @@
+//! C ABI of the core, for hosts other than JVM such as a node addon, python ctypes or an emacs module.
+//!
+//! Every function takes UTF-8, NUL-terminated strings and returns a JSON of `{"exit_code": i32, "text": string}`,
+//! which is the same as what JNI functions return. `exit_code` is 0 on success, and `text` holds the error otherwise.
+//! The returned string is owned by the caller, and must be released by [`gitnote_free`].
+//!
+//! `line` starts from 1, and 0 targets the whole file or directory.
+//! `content` is the unsaved content of the file to anchor lines to, which may be null to read the file on disk.
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::binding::{note_handler, NoteLibArgs, Response};
+use crate::path::PathResolver;
+
+/// Adds a note to the line of file.
+///
+/// # Safety
+///
+/// Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_add(
+    exec_path: *const c_char,
+    file_path: *const c_char,
+    line: i32,
+    message: *const c_char,
+    content: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let args = unsafe { lib_args(exec_path, file_path, line, Some(message), content) }?;
+        note_handler(&args.paths)?.add_note(&args)?;
+        Ok(String::new())
+    })
+}
+
+/// Reads the note of file, with messages anchored to the current content.
+///
+/// # Safety
+///
+/// Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_read(
+    exec_path: *const c_char,
+    file_path: *const c_char,
+    content: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let args = unsafe { lib_args(exec_path, file_path, 0, None, content) }?;
+        let note = note_handler(&args.paths)?.read_note(&args)?.opaque_note()?;
+        Ok(serde_json::to_string(&note)?)
+    })
+}
+
+/// Replaces the message on the line of file.
+///
+/// # Safety
+///
+/// Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_edit(
+    exec_path: *const c_char,
+    file_path: *const c_char,
+    line: i32,
+    message: *const c_char,
+    content: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let args = unsafe { lib_args(exec_path, file_path, line, Some(message), content) }?;
+        note_handler(&args.paths)?.edit_note(&args)?;
+        Ok(String::new())
+    })
+}
+
+/// Deletes the message on the line of file.
+///
+/// # Safety
+///
+/// Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_delete(
+    exec_path: *const c_char,
+    file_path: *const c_char,
+    line: i32,
+    content: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let args = unsafe { lib_args(exec_path, file_path, line, None, content) }?;
+        note_handler(&args.paths)?.delete_note(&args)?;
+        Ok(String::new())
+    })
+}
+
+/// Lists every note under `path`, which is either a file or a directory.
+///
+/// # Safety
+///
+/// Every pointer must be either null or a NUL-terminated string, which stays valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_list(
+    exec_path: *const c_char,
+    path: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let args = unsafe { lib_args(exec_path, path, 0, None, std::ptr::null()) }?;
+        let notes = note_handler(&args.paths)?.list_notes(&args)?.notes;
+        Ok(serde_json::to_string(&notes)?)
+    })
+}
+
+/// Releases a string returned by the other functions. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `response` must be either null or a string returned by the other functions, which is not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn gitnote_free(response: *mut c_char) {
+    if response.is_null() {
+        return;
+    }
+    drop(CString::from_raw(response));
+}
+
+/// # Safety
+///
+/// Same as the exported functions, see [`peel_str`].
+unsafe fn lib_args(
+    exec_path: *const c_char,
+    file_path: *const c_char,
+    line: i32,
+    message: Option<*const c_char>,
+    content: *const c_char,
+) -> anyhow::Result<NoteLibArgs> {
+    let exec_path = peel_str(exec_path)?.ok_or(anyhow!("`exec_path` is required"))?;
+    let file_path = peel_str(file_path)?.ok_or(anyhow!("`file_path` is required"))?;
+    let message = match message {
+        Some(message) => Some(peel_str(message)?.ok_or(anyhow!("`message` is required"))?),
+        None => None,
+    };
+    Ok(NoteLibArgs {
+        paths: PathResolver::resolve(Path::new(&exec_path), &file_path)?,
+        line: (line > 0).then_some(line),
+        message,
+        content: peel_str(content)?,
+    })
+}
+
+/// # Safety
+///
+/// `ptr` must be either null or a NUL-terminated string.
+unsafe fn peel_str(ptr: *const c_char) -> anyhow::Result<Option<String>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    let str = CStr::from_ptr(ptr).to_str()?;
+    Ok(Some(str.to_string()))
+}
+
+/// Runs the operation without letting any panic unwind across the ABI boundary, which is undefined behavior.
+fn respond<F>(operation: F) -> *mut c_char
+where
+    F: FnOnce() -> anyhow::Result<String> + panic::UnwindSafe,
+{
+    let result = panic::catch_unwind(operation)
+        .unwrap_or_else(|_| Err(anyhow!("unexpected panic in git-note core")));
+    let json = serde_json::to_string(&Response::of(result)).expect("Couldn't serialize response");
+    // serialized json never contains NUL, as it is escaped.
+    CString::new(json).expect("Couldn't create c string").into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, CStr, CString};
+    use std::ptr::null;
+
+    use serde_json::Value;
+
+    use crate::ffi::{gitnote_add, gitnote_free, gitnote_list, gitnote_read};
+    use crate::testlib::TestRepo;
+
+    fn take(response: *mut c_char) -> anyhow::Result<Value> {
+        let json = unsafe { CStr::from_ptr(response) }.to_str()?.to_string();
+        unsafe { gitnote_free(response) };
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+        let exec_path = CString::new(repo.path().to_str().unwrap())?;
+        let file_path = CString::new("test.txt")?;
+        let message = CString::new("hello")?;
+
+        // when
+        let added = take(unsafe { gitnote_add(exec_path.as_ptr(), file_path.as_ptr(), 2, message.as_ptr(), null()) })?;
+        let duplicated = take(unsafe { gitnote_add(exec_path.as_ptr(), file_path.as_ptr(), 2, message.as_ptr(), null()) })?;
+        let read = take(unsafe { gitnote_read(exec_path.as_ptr(), file_path.as_ptr(), null()) })?;
+        let listed = take(unsafe { gitnote_list(exec_path.as_ptr(), CString::new(".")?.as_ptr()) })?;
+
+        // then
+        assert_eq!(added["exit_code"], 0);
+        assert_ne!(duplicated["exit_code"], 0);
+        assert!(duplicated["text"].as_str().unwrap().contains("already exists"));
+        let note: Value = serde_json::from_str(read["text"].as_str().unwrap())?;
+        assert_eq!(note["messages"][0]["message"], "hello");
+        let notes: Value = serde_json::from_str(listed["text"].as_str().unwrap())?;
+        assert_eq!(notes[0]["reference"], "test.txt");
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+/// The notes ref of git which messages are exported to by default, i.e. `refs/notes/gitnote`.
+/// Shown by `git log --notes=gitnote`.
+pub const GIT_NOTES_REF: &str = "gitnote";
+
+/// A message on a line, as it is written in a note of git attached to a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitNoteLine {
+    pub reference: PathBuf,
+    /// the line in the file at the commit, which starts from 0.
+    pub line: usize,
+    pub author: Option<String>,
+    pub message: String,
+}
+
+/// Writes every message as `src/main.rs:3 (Jane Doe <jane@example.com>): message`, where the line starts from 1.
+/// The rest of a message of several lines follows, indented by 4 spaces.
+pub fn summarise(lines: &[CommitNoteLine]) -> String {
+    let mut summary = String::new();
+    for line in lines {
+        let author = line.author.as_ref().map(|author| format!(" ({})", author)).unwrap_or_default();
+        let mut message_lines = line.message.lines();
+        summary += &format!(
+            "{}:{}{}: {}\n",
+            line.reference.display(),
+            line.line + 1, // starts from 1
+            author,
+            message_lines.next().unwrap_or_default()
+        );
+        for rest in message_lines {
+            summary += &format!("    {}\n", rest);
+        }
+    }
+    return summary;
+}
+
+/// Reads back what [`summarise`] writes. Lines of other formats are ignored, e.g. notes written by hand.
+pub fn parse_summary(summary: &str) -> Vec<CommitNoteLine> {
+    let head = Regex::new(r"^(?P<path>[^\s].*?):(?P<line>[1-9]\d*)(?: \((?P<author>[^)]*)\))?: (?P<message>.*)$")
+        .expect("summary pattern is always valid");
+    let mut lines: Vec<CommitNoteLine> = Vec::new();
+    let mut continued = false;
+    for row in summary.lines() {
+        if let Some(captures) = head.captures(row) {
+            lines.push(CommitNoteLine {
+                reference: PathBuf::from(&captures["path"]),
+                line: captures["line"].parse::<usize>().expect("digits are always a number") - 1,
+                author: captures.name("author").map(|a| a.as_str().to_string()),
+                message: captures["message"].to_string(),
+            });
+            continued = true;
+            continue;
+        }
+        match (row.strip_prefix("    "), lines.last_mut()) {
+            (Some(rest), Some(last)) if continued => {
+                last.message.push('\n');
+                last.message.push_str(rest);
+            }
+            _ => continued = false,
+        }
+    }
+    return lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::gitnotes::{parse_summary, summarise, CommitNoteLine};
+
+    #[test]
+    fn summary_round_trip() -> anyhow::Result<()> {
+        // given
+        let lines = vec![
+            CommitNoteLine { reference: PathBuf::from("src/main.rs"), line: 2, author: Some("Jane <jane@example.com>".to_string()), message: "first\nsecond".to_string() },
+            CommitNoteLine { reference: PathBuf::from("a b.txt"), line: 0, author: None, message: "plain: with colon".to_string() },
+        ];
+
+        // when
+        let summary = summarise(&lines);
+        let written_by_hand = format!("Reviewed.\n{}\n    not continued", summary);
+
+        // then
+        assert_eq!(summary, "src/main.rs:3 (Jane <jane@example.com>): first\n    second\na b.txt:1: plain: with colon\n");
+        assert_eq!(parse_summary(&summary), lines);
+        assert_eq!(parse_summary(&written_by_hand), lines);
+        Ok(())
+    }
+}
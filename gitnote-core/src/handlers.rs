@@ -1,11 +1,23 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
 use anyhow::anyhow;
-use colored::Colorize;
-use unicode_width::UnicodeWidthStr;
+use chrono::{DateTime, Utc};
+use similar::TextDiff;
 
-use crate::libgit::Libgit;
-use crate::note::NoteLedger;
+use crate::comment::{harvest, harvest_where, inline, CommentSyntax, InlineComment};
+use crate::config::Storage;
+use crate::error::NoteError;
+use crate::diff::{noted_hunks, NotedHunk};
+use crate::export::{Excerpt, ReviewComment};
+use crate::gitnotes::{parse_summary, summarise, CommitNoteLine};
+use crate::libgit::{author_of, charset_of, BlameLine, Libgit};
+use crate::note::{Message, Note, NoteLedger, Scope};
 use crate::path::Paths;
 use crate::repository::NoteRepository;
+use crate::store::Listing;
+use crate::utils::write_atomically;
 
 /// Arguments for note operations.
 /// Even though this trait can be separated into multiple traits, it is combined into one for simplicity.
@@ -27,6 +39,91 @@ pub trait NoteArgs {
     ///
     /// Can be used for : add, edit
     fn message(&self) -> String;
+    /// whether the note targets a line, or the whole file without any line.
+    /// [`NoteArgs::user_line`] and [`NoteArgs::sys_line`] are not used unless it is [`Scope::Line`].
+    /// [`Scope::File`] is taken as [`Scope::Directory`] when the path is a directory.
+    ///
+    /// Can be used for : add, edit, delete
+    fn scope(&self) -> Scope {
+        Scope::Line
+    }
+    /// content of the file which is not saved yet, e.g. a buffer of editor.
+    /// Lines are anchored to this content instead of the file on disk, if given.
+    ///
+    /// Can be used for : add, read, edit, delete
+    fn content(&self) -> Option<String> {
+        None
+    }
+    /// tags to categorize the message, e.g. `todo` or `security`.
+    ///
+    /// Can be used for : add
+    fn tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// who writes the message. Resolved from git config like the author of a commit, if not given.
+    ///
+    /// Can be used for : add
+    fn author(&self) -> Option<String> {
+        None
+    }
+    /// narrows down messages to list.
+    ///
+    /// Can be used for : list
+    fn query(&self) -> NoteQuery {
+        NoteQuery::default()
+    }
+}
+
+/// Conditions of messages to list, which are all met by a matching message. An empty query matches every message.
+#[derive(Debug, Default, Clone)]
+pub struct NoteQuery {
+    pub tag: Option<String>,
+    /// a part of author, case-insensitively, e.g. a name or an email.
+    pub author: Option<String>,
+    /// messages created at or after, inclusively.
+    pub since: Option<DateTime<Utc>>,
+    /// messages created at or before, inclusively.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl NoteQuery {
+    pub fn matches(&self, message: &Message) -> bool {
+        let tag = self.tag.as_ref().is_none_or(|tag| message.tags.contains(tag));
+        let author = self.author.as_ref().is_none_or(|author| {
+            message.author.as_ref().is_some_and(|a| a.to_lowercase().contains(&author.to_lowercase()))
+        });
+        let since = self.since.is_none_or(|since| message.created_at() >= &since);
+        let until = self.until.is_none_or(|until| message.created_at() <= &until);
+        tag && author && since && until
+    }
+}
+
+/// Describes what the note of given args targets, to be used in messages to user.
+pub fn target_of<A>(args: &A) -> String
+where
+    A: NoteArgs,
+{
+    match scope_of(args) {
+        Ok(Scope::Line) => format!("line {}", args.user_line()),
+        Ok(Scope::Directory) => "the directory".to_string(),
+        _ => "the file".to_string(),
+    }
+}
+
+fn or_head(rev: &str) -> &str {
+    if rev.is_empty() { "HEAD" } else { rev }
+}
+
+fn scope_of<A>(args: &A) -> anyhow::Result<Scope>
+where
+    A: NoteArgs,
+{
+    let is_dir = args.paths().canonical().is_dir();
+    match args.scope() {
+        Scope::Line if is_dir => Err(anyhow!("{} is a directory, which has no line. omit the line to note the whole directory.", args.paths())),
+        Scope::File if is_dir => Ok(Scope::Directory),
+        scope => Ok(scope),
+    }
 }
 
 /// A core service to handle note operations.
@@ -49,12 +146,25 @@ where
     where
         A: NoteArgs,
     {
-        let ledger = self.note_repository.read_note(args.paths())?;
-        if ledger.opaque_exists(args.sys_line()) {
-            return Err(anyhow!("comment already exists for line {} in {}. consider to use `edit` instead.", args.user_line(), args.paths()));
+        let paths = args.paths();
+        let _lock = self.note_repository.lock_note(paths)?;
+        let ledger = self.note_repository.read_note(paths)?.with_buffer(args.content());
+        if self.find_uuid(&ledger, args)?.is_some() {
+            return Err(anyhow!("comment already exists for {} in {}. consider to use `edit` instead.", target_of(args), paths));
+        }
+        let author = args.author().or_else(|| author_of(paths));
+        match scope_of(args)? {
+            Scope::Line => {
+                if self.note_repository.is_binary(paths)? {
+                    return Err(NoteError::Binary(paths.relative()).into());
+                }
+                ledger.append(args.sys_line(), args.message(), author, args.tags())?
+            }
+            scope => ledger.append_whole(scope, args.message(), author, args.tags())?,
         }
-        ledger.append(args.sys_line(), args.message())?;
-        self.note_repository.write_note(args.paths(), &ledger.plain_note())?;
+        let note = ledger.plain_note();
+        self.note_repository.write_note(paths, &note)?;
+        self.note_repository.retain_note_snapshots(paths, &note)?;
         return Ok(());
     }
 
@@ -62,23 +172,348 @@ where
     where
         A: NoteArgs,
     {
-        let ledger = self.note_repository.read_note(args.paths())?;
+        let ledger = self.note_repository.read_note(args.paths())?.with_buffer(args.content());
         return Ok(ledger);
     }
 
-    pub fn edit_note<A>(&self, args: &A) -> anyhow::Result<()>
+    /// Reads every note under the path of given args, with messages which are still valid.
+    /// Notes are sorted by their path, and the ones without any message are omitted.
+    /// Notes which cannot be read are skipped, and told in [`Listing::skipped`].
+    pub fn list_notes<A>(&self, args: &A) -> anyhow::Result<Listing>
+    where
+        A: NoteArgs,
+    {
+        let paths = args.paths();
+        let base = paths.relative();
+        let query = args.query();
+        let Listing { notes: listed, mut skipped } = self.note_repository.read_all_notes(paths)?;
+        let mut notes: Vec<Note> = Vec::new();
+        for note in listed.into_iter().filter(|note| note.reference.starts_with(&base)) {
+            let reference = note.reference.clone();
+            let paths = Paths::new(paths.root(), reference.clone());
+            // e.g. the charset of the file is unknown, which is reported rather than hiding its messages.
+            let mut note = match self.note_repository.ledger(&paths, note).opaque_note() {
+                Ok(note) => note,
+                Err(e) => {
+                    skipped.push((reference, e));
+                    continue;
+                }
+            };
+            note.messages.retain(|m| query.matches(m));
+            if !note.messages.is_empty() {
+                notes.push(note);
+            }
+        }
+        notes.sort_by(|a, b| a.reference.cmp(&b.reference));
+        return Ok(Listing { notes, skipped });
+    }
+
+    /// Reads every message under the path of given args like [`NoteHandler::list_notes`],
+    /// along with `radius` lines around its line of the current content. Notes which cannot be read are skipped.
+    pub fn export_notes<A>(&self, args: &A, radius: usize) -> anyhow::Result<Vec<Excerpt>>
+    where
+        A: NoteArgs,
+    {
+        let mut excerpts = Vec::new();
+        for note in self.list_notes(args)?.notes {
+            let paths = Paths::new(args.paths().root(), note.reference.clone());
+            let content = note.messages.iter()
+                .any(|m| m.scope == Scope::Line)
+                .then(|| self.note_repository.read_note(&paths).and_then(|ledger| ledger.content()).ok())
+                .flatten();
+            excerpts.extend(note.messages.iter().map(|m| Excerpt::of(&note.reference, m, content.as_deref(), radius)));
+        }
+        return Ok(excerpts);
+    }
+
+    /// The commit which last changed each line of the file, to tell whether a noted line changed after its message was written.
+    /// Lines are of [`NoteArgs::content`] if given, just as [`NoteHandler::read_note`] anchors them.
+    pub fn blame<A>(&self, args: &A) -> anyhow::Result<Vec<BlameLine>>
+    where
+        A: NoteArgs,
+    {
+        return self.note_repository.libgit().blame(args.paths(), args.content().as_ref());
+    }
+
+    /// Splits the change of `range` into hunks like `git diff`, with messages in or near each hunk,
+    /// predicting which messages the change invalidates. Files without any noted hunk are left out.
+    ///
+    /// `range` is either none for the working tree against the index, `A` for the working tree against a commit,
+    /// or `A..B` between two commits, where an omitted side is `HEAD`.
+    pub fn diff_notes(&self, paths: &Paths, range: Option<&str>, radius: usize) -> anyhow::Result<Vec<(PathBuf, Vec<NotedHunk>)>> {
+        let libgit = self.note_repository.libgit();
+        let (old, new) = match range {
+            None => (None, None),
+            Some(range) if range.contains("...") => return Err(anyhow!("symmetric range `{}` is not supported. use `A..B` instead.", range)),
+            Some(range) => match range.split_once("..") {
+                Some((old, new)) => (Some(or_head(old)), Some(or_head(new))),
+                None => (Some(range), None),
+            },
+        };
+        let old = old.map(|rev| libgit.resolve_commit(paths, rev)).transpose()?;
+        let new = new.map(|rev| libgit.resolve_commit(paths, rev)).transpose()?;
+
+        let mut diffs = Vec::new();
+        for reference in libgit.diff_files(paths, old.as_ref(), new.as_ref())? {
+            if !reference.starts_with(paths.relative()) {
+                continue;
+            }
+            let paths = Paths::new(paths.root(), reference.clone());
+            // added by the change, which has no note to be invalidated.
+            let Some(old_blob) = libgit.read_rev_blob(&paths, old.as_ref().unwrap_or(&String::new()))? else {
+                continue;
+            };
+            let ledger = self.note_repository.read_note(&paths)?;
+            let messages = ledger.opaque_note_at(&old_blob)?.messages;
+            if messages.is_empty() {
+                continue;
+            }
+            // removed by the change, which invalidates every message.
+            let new_content = match &new {
+                Some(new) => libgit.read_rev_blob(&paths, new)?.map(|blob| blob.content),
+                None => paths.canonical().is_file().then(|| ledger.content()).transpose()?,
+            }.unwrap_or_default();
+            let hunks: Vec<NotedHunk> = noted_hunks(libgit.differ(), &old_blob.content, &new_content, &messages, radius)
+                .into_iter()
+                .filter(|hunk| !hunk.notes.is_empty())
+                .collect();
+            if !hunks.is_empty() {
+                diffs.push((reference, hunks));
+            }
+        }
+        return Ok(diffs);
+    }
+
+    /// Reads every message on lines under the path of given args as a review comment, anchored to the files at `HEAD`
+    /// instead of the working tree, as review comments are on commits.
+    /// Messages whose line is not at `HEAD`, e.g. on lines not committed yet, are left out, and so are notes which cannot be read.
+    pub fn review_comments<A>(&self, args: &A) -> anyhow::Result<Vec<ReviewComment>>
     where
         A: NoteArgs,
     {
         let paths = args.paths();
+        let base = paths.relative();
+        let libgit = self.note_repository.libgit();
+        let commit = libgit.resolve_commit(paths, "HEAD")?;
+        let mut notes = self.note_repository.read_all_notes(paths)?.notes;
+        notes.retain(|note| note.reference.starts_with(&base));
+        notes.sort_by(|a, b| a.reference.cmp(&b.reference));
+
+        let mut comments = Vec::new();
+        for note in notes {
+            let paths = Paths::new(paths.root(), note.reference.clone());
+            let Some(head_blob) = libgit.read_rev_blob(&paths, &commit)? else {
+                continue;
+            };
+            let reference = note.reference.clone();
+            let note = self.note_repository.ledger(&paths, note).opaque_note_at(&head_blob)?;
+            comments.extend(note.messages.iter()
+                .filter(|m| m.scope == Scope::Line)
+                .map(|m| ReviewComment::of(&reference, m, &commit)));
+        }
+        return Ok(comments);
+    }
+
+    /// Turns marker comments, e.g. `// NOTE(jane): explains why`, into messages at the lines they describe,
+    /// for the file of given paths or every file under the directory. Returns how many messages are imported.
+    ///
+    /// Files ignored by git, hidden files, symlinks and files of unknown comment syntax are skipped,
+    /// and so are markers whose line already has a message.
+    /// If `strip` is set, the markers are removed from files, and the messages are anchored to the files without them.
+    /// Stripped files keep their charset, line endings and permissions.
+    pub fn import_comments(&self, paths: &Paths, marker: &str, strip: bool) -> anyhow::Result<usize> {
+        if !paths.canonical().is_dir() {
+            return self.import_file_comments(paths, marker, strip);
+        }
+        let mut imported = 0;
+        for file in self.note_repository.libgit().listed_files(paths)? {
+            let hidden = file.strip_prefix(paths.relative()).unwrap_or(&file)
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+            imported += self.import_file_comments(&Paths::new(paths.root(), file), marker, strip)?;
+        }
+        return Ok(imported);
+    }
+
+    fn import_file_comments(&self, paths: &Paths, marker: &str, strip: bool) -> anyhow::Result<usize> {
+        // a submodule is listed as a directory, and a file deleted but not staged is listed as well.
+        let Ok(metadata) = fs::symlink_metadata(paths.canonical()) else {
+            return Ok(0);
+        };
+        if !metadata.is_file() {
+            return Ok(0);
+        }
+        let Some(syntax) = CommentSyntax::of(&paths.relative()) else {
+            return Ok(0);
+        };
+        if self.note_repository.is_binary(paths)? {
+            return Ok(0);
+        }
+        if harvest(&self.note_repository.read_note(paths)?.content()?, syntax, marker).markers.is_empty() {
+            return Ok(0);
+        }
+        let _lock = self.note_repository.lock_note(paths)?;
+        // read again under the lock, which other writers may have changed meanwhile.
         let ledger = self.note_repository.read_note(paths)?;
+        // a line already noted keeps its marker, as well as a second marker on a line.
+        let mut lines: HashSet<usize> = ledger.opaque_note()?.messages.iter()
+            .filter(|m| m.scope == Scope::Line)
+            .map(|m| m.line)
+            .collect();
+        let harvest = harvest_where(&ledger.content()?, syntax, marker, |found| lines.insert(found.line));
+        if harvest.markers.is_empty() {
+            return Ok(0);
+        }
+        // anchored to the stripped content, which is written to the file only after the note is.
+        let ledger = if strip { ledger.with_buffer(Some(harvest.stripped.clone())) } else { ledger };
+        let imported = harvest.markers.len();
+        for marker in harvest.markers {
+            let line = if strip { marker.stripped_line } else { marker.line };
+            let author = marker.author.or_else(|| author_of(paths));
+            ledger.append(line, marker.message, author, Vec::new())?;
+        }
+        let note = ledger.plain_note();
+        self.note_repository.write_note(paths, &note)?;
+        self.note_repository.retain_note_snapshots(paths, &note)?;
+        if strip {
+            write_atomically(&paths.canonical(), &charset_of(paths)?.encode(&harvest.stripped)?)?;
+        }
+        return Ok(imported);
+    }
+
+    /// Writes messages on lines under the path of given args into their files as marker comments, which [`NoteHandler::import_comments`] reads back.
+    /// Returns a patch in unified diff to be applied by `git apply`, leaving both files and notes as they are.
+    /// Files of unknown comment syntax are left out, and so are notes which cannot be read.
+    pub fn inline_notes<A>(&self, args: &A, marker: &str) -> anyhow::Result<String>
+    where
+        A: NoteArgs,
+    {
+        let mut patch = String::new();
+        for note in self.list_notes(args)?.notes {
+            let Some(syntax) = CommentSyntax::of(&note.reference) else {
+                continue;
+            };
+            let comments: Vec<InlineComment> = note.messages.iter()
+                .filter(|m| m.scope == Scope::Line)
+                .map(|m| InlineComment { line: m.line, author: m.author.clone(), message: m.message.clone() })
+                .collect();
+            if comments.is_empty() {
+                continue;
+            }
+            let paths = Paths::new(args.paths().root(), note.reference.clone());
+            let content = self.note_repository.read_note(&paths)?.content()?;
+            let inlined = inline(&content, syntax, marker, &comments);
+            let reference = note.reference.display();
+            patch += &TextDiff::from_lines(&content, &inlined)
+                .unified_diff()
+                .header(&format!("a/{}", reference), &format!("b/{}", reference))
+                .to_string();
+        }
+        return Ok(patch);
+    }
+
+    /// Summarises messages on lines of files changed by the commit into a note of git, in `refs/notes/<notes_ref>`,
+    /// so that `git log --notes=<notes_ref>` shows them. Lines are of the files at the commit.
+    /// Returns how many messages are exported, where nothing is written if none.
+    pub fn export_git_notes(&self, paths: &Paths, rev: &str, notes_ref: &str) -> anyhow::Result<usize> {
+        let libgit = self.note_repository.libgit();
+        let commit = libgit.resolve_commit(paths, rev)?;
+        let mut lines = Vec::new();
+        for reference in libgit.changed_files(paths, &commit)? {
+            let paths = Paths::new(paths.root(), reference.clone());
+            // removed by the commit.
+            let Some(rev_blob) = libgit.read_rev_blob(&paths, &commit)? else {
+                continue;
+            };
+            let ledger = self.note_repository.read_note(&paths)?;
+            let charset = charset_of(&paths)?;
+            for m in ledger.plain_note().messages.iter().filter(|m| m.scope == Scope::Line) {
+                let Ok(old_blob) = libgit.read_git_blob_as(&paths, &m.oid, charset) else {
+                    continue;
+                };
+                if let Some(line) = m.reanchor(libgit.differ(), &old_blob.content, &rev_blob.content) {
+                    lines.push(CommitNoteLine { reference: reference.clone(), line, author: m.author.clone(), message: m.message.clone() });
+                }
+            }
+        }
+        if !lines.is_empty() {
+            libgit.write_git_note(paths, notes_ref, &commit, &summarise(&lines))?;
+        }
+        return Ok(lines.len());
+    }
+
+    /// Reads back notes of git in `refs/notes/<notes_ref>` written by [`NoteHandler::export_git_notes`], as messages anchored to the files at those commits.
+    /// Messages already noted on the same line are skipped, so that it can be run repeatedly. Returns how many messages are imported.
+    pub fn import_git_notes(&self, paths: &Paths, notes_ref: &str) -> anyhow::Result<usize> {
+        let libgit = self.note_repository.libgit();
+        let mut imported = 0;
+        for (commit, summary) in libgit.read_git_notes(paths, notes_ref)? {
+            for line in parse_summary(&summary) {
+                let paths = Paths::new(paths.root(), line.reference.clone());
+                let Some(rev_blob) = libgit.read_rev_blob(&paths, &commit)? else {
+                    continue;
+                };
+                let _lock = self.note_repository.lock_note(&paths)?;
+                let ledger = self.note_repository.read_note(&paths)?;
+                let charset = charset_of(&paths)?;
+                let noted = ledger.plain_note().messages.iter().any(|m| {
+                    m.message == line.message && libgit.read_git_blob_as(&paths, &m.oid, charset)
+                        .is_ok_and(|old_blob| m.reanchor(libgit.differ(), &old_blob.content, &rev_blob.content) == Some(line.line))
+                });
+                if noted || rev_blob.snippet(line.line).is_none() {
+                    continue;
+                }
+                ledger.append_to(&rev_blob, line.line, line.message, line.author, Vec::new())?;
+                self.note_repository.write_note(&paths, &ledger.plain_note())?;
+                imported += 1;
+            }
+        }
+        if imported > 0 {
+            self.note_repository.retain_snapshots(paths)?;
+        }
+        return Ok(imported);
+    }
+
+    /// Rewrites every note file of the repository in the latest format, returning how many are rewritten.
+    pub fn migrate_notes(&self, paths: &Paths) -> anyhow::Result<usize> {
+        return self.note_repository.migrate_all(paths);
+    }
+
+    /// Moves every note of the repository into given storage, returning how many notes are moved.
+    pub fn convert_notes(&self, paths: &Paths, to: Storage) -> anyhow::Result<usize> {
+        return self.note_repository.convert(paths, to);
+    }
 
-        return if let Some(uuid) = ledger.opaque_uuid(args.sys_line()) {
+    /// The file which the note of given paths is stored in, to watch changes by other processes.
+    pub fn note_source(&self, paths: &Paths) -> anyhow::Result<Option<PathBuf>> {
+        return self.note_repository.note_source(paths);
+    }
+
+    pub fn edit_note<A>(&self, args: &A) -> anyhow::Result<()>
+    where
+        A: NoteArgs,
+    {
+        let paths = args.paths();
+        let not_found = || anyhow!("no comment found for {} in {}. consider to use `add` instead.", target_of(args), paths);
+        // a note never written has nothing to edit, which is told without touching `.git`.
+        // otherwise the note is read only under the lock, as it may be written in between.
+        if self.note_repository.never_written(paths)? {
+            return Err(not_found());
+        }
+        let _lock = self.note_repository.lock_note(paths)?;
+        let ledger = self.note_repository.read_note(paths)?.with_buffer(args.content());
+
+        return if let Some(uuid) = self.find_uuid(&ledger, args)? {
             ledger.edit(uuid, args.message());
-            self.note_repository.write_note(paths, &ledger.plain_note())?;
+            let note = ledger.plain_note();
+            self.note_repository.write_note(paths, &note)?;
+            self.note_repository.retain_note_snapshots(paths, &note)?;
             Ok(())
         } else {
-            Err(anyhow!("no comment found for line {} in {}. consider to use `add` instead.", args.user_line(), paths))
+            Err(not_found())
         };
     }
 
@@ -87,39 +522,143 @@ where
         A: NoteArgs,
     {
         let paths = args.paths();
-        let ledger = self.note_repository.read_note(paths)?;
+        let not_found = || anyhow!("no comment found for {} in {}", target_of(args), paths);
+        // same as `edit_note`, nothing is read out of the lock.
+        if self.note_repository.never_written(paths)? {
+            return Err(not_found());
+        }
+        let _lock = self.note_repository.lock_note(paths)?;
+        let ledger = self.note_repository.read_note(paths)?.with_buffer(args.content());
 
-        return if let Some(uuid) = ledger.opaque_uuid(args.sys_line()) {
+        return if let Some(uuid) = self.find_uuid(&ledger, args)? {
             ledger.delete(uuid);
             self.note_repository.write_note(paths, &ledger.plain_note())?;
+            // lets the snapshot of deleted message go.
+            self.note_repository.retain_snapshots(paths)?;
             Ok(())
         } else {
-            Err(anyhow!("no comment found for line {} in {}", args.user_line(), paths))
+            Err(not_found())
+        };
+    }
+
+    fn find_uuid<A>(&self, ledger: &NoteLedger<T>, args: &A) -> anyhow::Result<Option<String>>
+    where
+        A: NoteArgs,
+    {
+        return match scope_of(args)? {
+            Scope::Line => ledger.opaque_uuid(args.sys_line()),
+            scope => Ok(ledger.whole_uuid(&scope)),
         };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::diff::{Differ, SimilarDiffer};
-    use crate::handlers::{NoteArgs, NoteHandler};
-    use crate::libgit::{Libgit, ManualLibgit};
-    use crate::note::Note;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use itertools::Itertools;
+
+    use crate::diff::SimilarDiffer;
+    use crate::handlers::NoteHandler;
+    use crate::error::NoteError;
+    use crate::libgit::{Libgit, ManualLibgit, ProcessLibgit};
+    use crate::note::{Note, Scope};
     use crate::path::{PathResolver, Paths};
     use crate::repository::NoteRepository;
-    use crate::testlib::TestRepo;
+    use crate::store::MemoryStore;
+    use crate::testlib::{FakeLibgit, TestArgs, TestRepo};
+
+    /// Every scenario below runs against each [`Libgit`] backend, as they should behave the same.
+    macro_rules! conformance {
+        ($($scenario:ident),* $(,)?) => {
+            mod manual {
+                use super::*;
+
+                $(
+                    #[test]
+                    fn $scenario() -> anyhow::Result<()> {
+                        super::$scenario(ManualLibgit::new(SimilarDiffer))
+                    }
+                )*
+            }
+
+            mod process {
+                use super::*;
+
+                $(
+                    #[test]
+                    fn $scenario() -> anyhow::Result<()> {
+                        super::$scenario(ProcessLibgit::new(SimilarDiffer))
+                    }
+                )*
+            }
+        };
+    }
+
+    #[test]
+    fn without_repository() -> anyhow::Result<()> {
+        // given
+        let libgit = FakeLibgit::new();
+        libgit.write("src/a.txt", "foo\nbar\nbaz");
+        let paths = Paths::new(PathBuf::from("/nowhere"), PathBuf::from("src/a.txt"));
+        let note_handler = NoteHandler::new(NoteRepository::with_store(libgit, Box::new(MemoryStore::new())));
+        let args = |line: usize, message: &str| TestArgs::line(&paths, line, message);
 
-    struct Sut {
+        // when
+        note_handler.add_note(&args(2, "on bar"))?;
+        note_handler.add_note(&args(3, "on baz"))?;
+        note_handler.edit_note(&args(3, "baz again"))?;
+        let note = note_handler.read_note(&args(0, ""))?.with_buffer(Some("new\nfoo\nbar\nqux".to_string())).opaque_note()?;
+
+        // then
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].message, "on bar");
+        assert_eq!(note.messages[0].line, 2);
+        let root = Paths::new(PathBuf::from("/nowhere"), PathBuf::new());
+        let listed = note_handler.list_notes(&TestArgs::file(&root, ""))?.notes;
+        assert_eq!(listed[0].messages.len(), 2);
+        Ok(())
+    }
+
+    conformance!(
+        add_note,
+        read_note,
+        edit_note,
+        delete_note,
+        survive_gc,
+        retain_snapshots,
+        refuse_binary,
+        file_note,
+        directory_note,
+        list_notes,
+        unknown_charset,
+        unsaved_buffer,
+        read_only,
+        read_only_home,
+        comments_round_trip,
+        keep_markers_not_imported,
+        git_notes_round_trip,
+        review_comments,
+        diff_notes,
+    );
+
+    struct Sut<L>
+    where
+        L: Libgit,
+    {
         repo: TestRepo,
         paths: Paths,
-        note_handler: NoteHandler<ManualLibgit<SimilarDiffer>>,
+        note_handler: NoteHandler<L>,
     }
 
-    impl Sut {
-        fn setup(content: &str) -> anyhow::Result<Self> {
+    impl<L> Sut<L>
+    where
+        L: Libgit,
+    {
+        fn setup(libgit: L, content: &str) -> anyhow::Result<Self> {
             let repo = TestRepo::new();
             let _ = repo.create_file("test.txt", Some(content))?;
-            let libgit = ManualLibgit::new(SimilarDiffer);
             let paths = PathResolver::resolve(repo.path(), "test.txt")?;
 
             let repository = NoteRepository::new(libgit);
@@ -128,41 +667,12 @@ mod tests {
         }
     }
 
-    struct TestNoteArgs {
-        paths: Paths,
-        line: usize,
-        message: String,
-    }
-
-    impl NoteArgs for TestNoteArgs {
-        fn paths(&self) -> &Paths {
-            &self.paths
-        }
-
-        fn user_line(&self) -> usize {
-            self.line
-        }
-
-        fn sys_line(&self) -> usize {
-            self.line - 1
-        }
-
-        fn message(&self) -> String {
-            self.message.clone()
-        }
-    }
-
-    #[test]
-    fn test_add_note() -> anyhow::Result<()> {
+    fn add_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
         // given
-        let sut = Sut::setup("foo\nbar\nbaz")?;
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
 
         // when
-        let args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "hello".to_string(),
-        };
+        let args = TestArgs::line(&sut.paths, 2, "hello");
         sut.note_handler.add_note(&args)?;
 
         // then
@@ -179,23 +689,14 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_read_note() -> anyhow::Result<()> {
+    fn read_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
         // given
-        let sut = Sut::setup("foo\nbar\nbaz")?;
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
 
         // when
-        let add_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "hello".to_string(),
-        };
+        let add_args = TestArgs::line(&sut.paths, 2, "hello");
         sut.note_handler.add_note(&add_args)?;
-        let read_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 0,
-            message: "".to_string(),
-        };
+        let read_args = TestArgs::line(&sut.paths, 0, "");
         let ledger = sut.note_handler.read_note(&read_args)?;
 
         // then
@@ -209,23 +710,14 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn edit_note() -> anyhow::Result<()> {
+    fn edit_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
         // given
-        let sut = Sut::setup("foo\nbar\nbaz")?;
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
 
         // when
-        let add_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "hello".to_string(),
-        };
+        let add_args = TestArgs::line(&sut.paths, 2, "hello");
         sut.note_handler.add_note(&add_args)?;
-        let edit_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "world".to_string(),
-        };
+        let edit_args = TestArgs::line(&sut.paths, 2, "world");
         sut.note_handler.edit_note(&edit_args)?;
 
         // then
@@ -243,23 +735,14 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn delete_note() -> anyhow::Result<()> {
+    fn delete_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
         // given
-        let sut = Sut::setup("foo\nbar\nbaz")?;
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
 
         // when
-        let add_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "hello".to_string(),
-        };
+        let add_args = TestArgs::line(&sut.paths, 2, "hello");
         sut.note_handler.add_note(&add_args)?;
-        let delete_args = TestNoteArgs {
-            paths: sut.paths.clone(),
-            line: 2,
-            message: "".to_string(),
-        };
+        let delete_args = TestArgs::line(&sut.paths, 2, "");
         sut.note_handler.delete_note(&delete_args)?;
 
         // then
@@ -272,4 +755,402 @@ mod tests {
 
         Ok(())
     }
+
+    fn survive_gc<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
+        let args = TestArgs::line(&sut.paths, 2, "hello");
+        sut.note_handler.add_note(&args)?;
+
+        // when : the ref of snapshots is packed by the first gc, and another note is written after it
+        sut.repo.command("git gc --prune=now --quiet")?;
+        sut.repo.create_file("other.txt", Some("qux"))?;
+        let other = PathResolver::resolve(&sut.repo.path(), "other.txt")?;
+        sut.note_handler.add_note(&TestArgs::line(&other, 1, "on qux"))?;
+        sut.repo.command("git gc --prune=now --quiet")?;
+
+        // then
+        let ledger = sut.note_handler.read_note(&args)?;
+        let note = ledger.opaque_note()?;
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].line, 1);
+        assert_eq!(sut.note_handler.read_note(&TestArgs::file(&other, ""))?.opaque_note()?.messages.len(), 1);
+        Ok(())
+    }
+
+    fn retain_snapshots<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
+        let args = |line: usize, message: &str| TestArgs::line(&sut.paths, line, message);
+        let retained = || -> anyhow::Result<Vec<String>> {
+            let manual = ManualLibgit::new(SimilarDiffer).retained_blobs(&sut.paths)?;
+            assert_eq!(manual, ProcessLibgit::new(SimilarDiffer).retained_blobs(&sut.paths)?);
+            Ok(manual)
+        };
+        sut.note_handler.add_note(&args(1, "on foo"))?;
+        sut.repo.create_file("test.txt", Some("foo\nbar\nqux"))?;
+        sut.note_handler.add_note(&args(3, "on qux"))?;
+        let oids = sut.note_handler.read_note(&args(1, ""))?.plain_note().messages.iter().map(|m| m.oid.clone()).collect::<Vec<_>>();
+        assert_eq!(retained()?, oids.iter().sorted().cloned().collect::<Vec<_>>());
+
+        // when : the first snapshot is no longer referenced
+        sut.note_handler.delete_note(&args(1, ""))?;
+
+        // then
+        assert_eq!(retained()?, vec![oids[1].clone()]);
+
+        // when : another note is corrupt
+        sut.repo.create_file("other.txt", None)?;
+        let other = PathResolver::resolve(&sut.repo.path(), "other.txt")?;
+        let other_path = other.note(&Note::get_id(&other.relative())?)?;
+        other.initialize(&other_path)?;
+        fs::write(&other_path, r#"{"version":1,"id":"#)?;
+        sut.repo.create_file("test.txt", Some("new\nfoo\nbar\nqux"))?;
+        let edited = sut.note_handler.edit_note(&args(4, "qux again"));
+        let deleted = sut.note_handler.delete_note(&args(4, ""));
+
+        // then : writes succeed, and nothing is let go
+        assert!(edited.is_ok());
+        assert!(deleted.is_ok());
+        assert!(retained()?.contains(&oids[1]));
+        Ok(())
+    }
+
+    fn refuse_binary<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\0bar")?;
+
+        // when
+        let args = TestArgs::line(&sut.paths, 1, "hello");
+        let result = sut.note_handler.add_note(&args);
+
+        // then
+        let error = result.unwrap_err();
+        assert!(matches!(error.downcast_ref::<NoteError>(), Some(NoteError::Binary(_))));
+        Ok(())
+    }
+
+    fn file_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\0bar")?;
+        let args = TestArgs::file(&sut.paths, "hello");
+
+        // when
+        sut.note_handler.add_note(&args)?;
+
+        // then
+        assert!(sut.note_handler.add_note(&args).is_err());
+        let note = sut.note_handler.read_note(&args)?.opaque_note()?;
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].scope, Scope::File);
+        assert_eq!(note.messages[0].message, "hello");
+
+        let args = TestArgs::file(&sut.paths, "world");
+        sut.note_handler.edit_note(&args)?;
+        let note = sut.note_handler.read_note(&args)?.opaque_note()?;
+        assert_eq!(note.messages[0].message, "world");
+
+        sut.note_handler.delete_note(&args)?;
+        let note = sut.note_handler.read_note(&args)?.opaque_note()?;
+        assert!(note.messages.is_empty());
+        Ok(())
+    }
+
+    fn directory_note<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo")?;
+        sut.repo.create_dir("billing")?;
+        let paths = PathResolver::resolve(sut.repo.path(), "billing")?;
+
+        // when
+        let line_args = TestArgs::line(&paths, 1, "hello");
+        let args = TestArgs::file(&paths, "the legacy billing path");
+
+        // then
+        assert!(sut.note_handler.add_note(&line_args).is_err());
+        sut.note_handler.add_note(&args)?;
+        let note = sut.note_handler.read_note(&args)?.opaque_note()?;
+        assert_eq!(note.reference, paths.relative());
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].scope, Scope::Directory);
+        Ok(())
+    }
+
+    fn list_notes<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar")?;
+        sut.repo.create_dir("sub")?;
+        sut.repo.create_file("sub/nested.txt", Some("baz"))?;
+        let nested = PathResolver::resolve(sut.repo.path(), "sub/nested.txt")?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 2, "top"))?;
+        sut.note_handler.add_note(&TestArgs::file(&nested, "nested"))?;
+        // only read, which leaves no note
+        sut.repo.create_file("empty.txt", Some("qux"))?;
+        let empty = PathResolver::resolve(sut.repo.path(), "empty.txt")?;
+        sut.note_handler.read_note(&TestArgs::file(&empty, ""))?;
+
+        // when
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+        let all = sut.note_handler.list_notes(&TestArgs::file(&root, ""))?.notes;
+        let sub = PathResolver::resolve(sut.repo.path(), "sub")?;
+        let under_sub = sut.note_handler.list_notes(&TestArgs::file(&sub, ""))?.notes;
+
+        // then
+        assert_eq!(all.iter().map(|note| note.reference.clone()).collect::<Vec<_>>(), vec![nested.relative(), sut.paths.relative()]);
+        assert_eq!(all[1].messages[0].line, 1);
+        assert_eq!(under_sub.len(), 1);
+        assert_eq!(under_sub[0].messages[0].message, "nested");
+        Ok(())
+    }
+
+    fn unknown_charset<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar")?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 2, "hidden"))?;
+        sut.repo.create_file(".gitattributes", Some("*.txt working-tree-encoding=no-such-charset\n"))?;
+
+        // when
+        let read = sut.note_handler.read_note(&TestArgs::file(&sut.paths, "")).and_then(|ledger| ledger.opaque_note());
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+        let listing = sut.note_handler.list_notes(&TestArgs::file(&root, ""))?;
+
+        // then
+        assert!(read.is_err());
+        assert!(listing.notes.is_empty());
+        assert_eq!(listing.skipped.len(), 1);
+        Ok(())
+    }
+
+    fn unsaved_buffer<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
+        let args = TestArgs::line(&sut.paths, 2, "hello");
+        sut.note_handler.add_note(&args)?;
+
+        // when : a line is inserted in editor, but not saved yet
+        let buffer_args = TestArgs { content: Some("new\nfoo\nbar\nbaz".to_string()), ..TestArgs::line(&sut.paths, 1, "world") };
+        let note = sut.note_handler.read_note(&buffer_args)?.opaque_note()?;
+
+        // then
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].line, 2);
+
+        sut.note_handler.add_note(&buffer_args)?;
+        let note = sut.note_handler.read_note(&buffer_args)?.opaque_note()?;
+        let message = note.find(0).unwrap();
+        assert_eq!(message.message, "world");
+        assert_eq!(message.snippet, "new");
+
+        // a note on the unsaved line is gone from disk's view, which has no such line
+        let note = sut.note_handler.read_note(&args)?.opaque_note()?;
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].line, 1);
+        Ok(())
+    }
+
+    fn read_only<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar")?;
+        let home = sut.repo.path().join(".git/notes");
+        let args = TestArgs::line(&sut.paths, 1, "hello");
+
+        // when
+        let ledger = sut.note_handler.read_note(&args)?;
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+        let notes = sut.note_handler.list_notes(&TestArgs::file(&root, ""))?.notes;
+
+        let deleted = sut.note_handler.delete_note(&args);
+        let edited = sut.note_handler.edit_note(&args);
+
+        // then
+        assert!(ledger.opaque_note()?.messages.is_empty());
+        assert!(notes.is_empty());
+        assert!(deleted.is_err() && edited.is_err());
+        assert!(!home.exists());
+
+        // a note is created by the first write
+        sut.note_handler.add_note(&args)?;
+        assert!(home.join("config.yml").exists());
+        assert_eq!(sut.note_handler.read_note(&args)?.opaque_note()?.messages.len(), 1);
+        Ok(())
+    }
+
+    fn read_only_home<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar")?;
+        sut.repo.create_file("other.txt", Some("baz"))?;
+        let args = TestArgs::line(&sut.paths, 1, "hello");
+        let other = TestArgs::line(&PathResolver::resolve(sut.repo.path(), "other.txt")?, 1, "");
+        let root = TestArgs::file(&PathResolver::resolve(sut.repo.path(), ".")?, "");
+        sut.note_handler.add_note(&args)?;
+        #[cfg(unix)]
+        sut.repo.set_notes_readonly(true)?;
+        let before = sut.repo.git_files()?;
+
+        // when
+        let read = sut.note_handler.read_note(&args).and_then(|ledger| ledger.opaque_note());
+        let read_other = sut.note_handler.read_note(&other).and_then(|ledger| ledger.opaque_note());
+        let listed = sut.note_handler.list_notes(&root).map(|listing| listing.notes);
+        let exported = sut.note_handler.export_notes(&root, 1);
+        let deleted = sut.note_handler.delete_note(&other);
+
+        // then
+        let after = sut.repo.git_files();
+        #[cfg(unix)]
+        sut.repo.set_notes_readonly(false)?;
+        assert_eq!(read?.messages.len(), 1);
+        assert!(read_other?.messages.is_empty());
+        assert_eq!(listed?.len(), 1);
+        assert_eq!(exported?.len(), 1);
+        assert!(deleted.is_err());
+        assert_eq!(before, after?);
+        Ok(())
+    }
+
+    fn comments_round_trip<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo")?;
+        sut.repo.create_file("main.rs", Some("fn main() {\n    // NOTE(jane): explains why\n    run();\n}\n"))?;
+        let script = sut.repo.create_file("run.sh", Some("#!/bin/sh\r\necho hi # NOTE: greets\r\n"))?;
+        // ignored by git, so are files under it
+        sut.repo.create_file(".gitignore", Some("target/\n"))?;
+        sut.repo.create_file(".gitattributes", Some("*.py working-tree-encoding=euc-kr\n"))?;
+        // `# 한글` followed by a marker, in euc-kr
+        let legacy = sut.repo.path().join("legacy.py");
+        fs::write(&legacy, b"# \xc7\xd1\xb1\xdb\nx = 1 # NOTE: encoded\n")?;
+        sut.repo.create_dir("target")?;
+        sut.repo.create_file("target/generated.rs", Some("// NOTE: generated\nfn f() {}\n"))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+            // a loop, which is never followed
+            symlink(sut.repo.path(), sut.repo.path().join("loop"))?;
+        }
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+        let paths = PathResolver::resolve(sut.repo.path(), "main.rs")?;
+
+        // when
+        let imported = sut.note_handler.import_comments(&root, "NOTE", true)?;
+        let again = sut.note_handler.import_comments(&root, "NOTE", true)?;
+        let patch = sut.note_handler.inline_notes(&TestArgs::file(&root, ""), "NOTE")?;
+
+        // then
+        assert_eq!((imported, again), (3, 0));
+        assert_eq!(fs::read(&legacy)?, b"# \xc7\xd1\xb1\xdb\nx = 1\n");
+        assert_eq!(fs::read_to_string(paths.canonical())?, "fn main() {\n    run();\n}\n");
+        assert_eq!(fs::read_to_string(&script)?, "#!/bin/sh\r\necho hi\r\n");
+        assert_eq!(fs::read_to_string(sut.repo.path().join("target/generated.rs"))?, "// NOTE: generated\nfn f() {}\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&script)?.permissions().mode() & 0o777, 0o755);
+        }
+        let note = sut.note_handler.read_note(&TestArgs::file(&paths, ""))?.opaque_note()?;
+        assert_eq!(note.messages[0].line, 1);
+        assert_eq!(note.messages[0].author, Some("jane".to_string()));
+        assert!(patch.contains("--- a/main.rs\n+++ b/main.rs\n"), "{}", patch);
+        assert!(patch.contains(" fn main() {\n+    // NOTE(jane): explains why\n     run();\n"), "{}", patch);
+        Ok(())
+    }
+
+    fn keep_markers_not_imported<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo")?;
+        let main = sut.repo.create_file("main.rs", Some("fn main() {\n    // NOTE: noted already\n    run(); // NOTE: twice\n    stop(); // NOTE: new\n}\n"))?;
+        let paths = PathResolver::resolve(sut.repo.path(), "main.rs")?;
+        sut.note_handler.add_note(&TestArgs::line(&paths, 3, "existing"))?;
+
+        // when
+        let imported = sut.note_handler.import_comments(&paths, "NOTE", true)?;
+
+        // then
+        assert_eq!(imported, 1);
+        assert_eq!(fs::read_to_string(&main)?, "fn main() {\n    // NOTE: noted already\n    run(); // NOTE: twice\n    stop();\n}\n");
+        let note = sut.note_handler.read_note(&TestArgs::file(&paths, ""))?.opaque_note()?;
+        let noted = note.messages.iter().map(|m| (m.line, m.message.as_str())).sorted().collect::<Vec<_>>();
+        assert_eq!(noted, vec![(2, "existing"), (3, "new")]);
+        Ok(())
+    }
+
+    fn git_notes_round_trip<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar")?;
+        sut.repo.commit("first")?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 2, "on bar\nmore"))?;
+        // a line is inserted after the note is written.
+        sut.repo.create_file("test.txt", Some("new\nfoo\nbar"))?;
+        let second = sut.repo.commit("second")?;
+        sut.repo.commit("empty")?;
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+
+        // when
+        let exported = sut.note_handler.export_git_notes(&root, &second, "gitnote")?;
+        let none = sut.note_handler.export_git_notes(&root, "HEAD", "gitnote")?;
+        let git_notes = ProcessLibgit::new(SimilarDiffer).read_git_notes(&root, "gitnote")?;
+        // removed, and read back from the note of git.
+        sut.note_handler.delete_note(&TestArgs::line(&sut.paths, 3, ""))?;
+        let imported = sut.note_handler.import_git_notes(&root, "gitnote")?;
+        let again = sut.note_handler.import_git_notes(&root, "gitnote")?;
+
+        // then
+        assert_eq!((exported, none, imported, again), (1, 0, 1, 0));
+        assert_eq!(git_notes.len(), 1);
+        assert_eq!(git_notes[0].0, second);
+        assert!(git_notes[0].1.starts_with("test.txt:3") && git_notes[0].1.ends_with(": on bar\n    more"), "{}", git_notes[0].1);
+        let note = sut.note_handler.read_note(&TestArgs::file(&sut.paths, ""))?.opaque_note()?;
+        assert_eq!(note.messages.len(), 1);
+        assert_eq!(note.messages[0].line, 2);
+        assert_eq!(note.messages[0].message, "on bar\nmore");
+        Ok(())
+    }
+
+    /// The payload is compared with `testdata/review/comments.json`, which an upload step can be tried against.
+    fn review_comments<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz")?;
+        let commit = sut.repo.commit("first")?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 1, "on foo"))?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 3, "on baz\nwith a second line"))?;
+        sut.note_handler.add_note(&TestArgs::file(&sut.paths, "on the file"))?;
+        // not committed yet, which shifts lines of the working tree.
+        sut.repo.create_file("test.txt", Some("new\nfoo\nbar\nbaz"))?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 1, "on new"))?;
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+
+        // when
+        let comments = sut.note_handler.review_comments(&TestArgs::file(&root, ""))?;
+
+        // then
+        let fixture = std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/review/comments.json"))?;
+        let expected: serde_json::Value = serde_json::from_str(&fixture.replace("<commit>", &commit))?;
+        assert_eq!(serde_json::to_value(&comments)?, expected);
+        Ok(())
+    }
+
+    fn diff_notes<L: Libgit>(libgit: L) -> anyhow::Result<()> {
+        // given
+        let sut = Sut::setup(libgit, "foo\nbar\nbaz\nqux\n")?;
+        let first = sut.repo.commit("first")?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 2, "on bar"))?;
+        sut.note_handler.add_note(&TestArgs::line(&sut.paths, 3, "on baz"))?;
+        sut.repo.create_file("test.txt", Some("new\nfoo\nbar\nchanged\nqux\n"))?;
+        let root = PathResolver::resolve(sut.repo.path(), ".")?;
+
+        // when
+        let working = sut.note_handler.diff_notes(&root, None, 3)?;
+        sut.repo.commit("second")?;
+        let committed = sut.note_handler.diff_notes(&root, Some(&format!("{}..", first)), 3)?;
+        let nothing = sut.note_handler.diff_notes(&root, Some("HEAD"), 3)?;
+
+        // then
+        for diffs in [&working, &committed] {
+            assert_eq!(diffs.len(), 1);
+            let notes = &diffs[0].1[0].notes;
+            assert_eq!(notes.iter().map(|note| (note.message.line, note.new_line)).collect::<Vec<_>>(), vec![(1, Some(2)), (2, None)]);
+        }
+        assert!(nothing.is_empty());
+        assert!(sut.note_handler.diff_notes(&root, Some("HEAD...HEAD"), 3).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,142 @@
+use std::io::{BufRead, Write};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// A message from client, which is a request if it has `id`, otherwise a notification.
+#[derive(Debug, Deserialize)]
+pub struct Incoming {
+    #[serde(default)]
+    pub id: Option<Value>,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn ok(id: Value, result: Value) -> Self {
+        Response { jsonrpc: "2.0", id, result: Some(result), error: None }
+    }
+
+    pub fn err(id: Value, error: RpcError) -> Self {
+        Response { jsonrpc: "2.0", id, result: None, error: Some(error) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    jsonrpc: &'static str,
+    method: String,
+    params: Value,
+}
+
+impl Notification {
+    pub fn new(method: &str, params: Value) -> Self {
+        Notification { jsonrpc: "2.0", method: method.to_string(), params }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+/// Reads a message framed by `Content-Length` header, as LSP does.
+/// Returns `None` when the stream is closed.
+pub fn read_framed<R>(reader: &mut R) -> anyhow::Result<Option<String>>
+where
+    R: BufRead,
+{
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or(anyhow!("missing Content-Length header"))?;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8(body)?))
+}
+
+pub fn write_framed<W, M>(writer: &mut W, message: &M) -> anyhow::Result<()>
+where
+    W: Write,
+    M: Serialize,
+{
+    let body = serde_json::to_string(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a message on a single line, for clients which delimit messages by newlines.
+pub fn write_delimited<W, M>(writer: &mut W, message: &M) -> anyhow::Result<()>
+where
+    W: Write,
+    M: Serialize,
+{
+    let body = serde_json::to_string(message)?;
+    writeln!(writer, "{}", body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn framed_round_trip() -> anyhow::Result<()> {
+        let mut buffer = Vec::new();
+        write_framed(&mut buffer, &Notification::new("foo", json!({ "bar": "한글" })))?;
+        write_framed(&mut buffer, &Response::ok(json!(1), Value::Null))?;
+
+        let mut reader = Cursor::new(buffer);
+        let first: Value = serde_json::from_str(&read_framed(&mut reader)?.unwrap())?;
+        let second: Value = serde_json::from_str(&read_framed(&mut reader)?.unwrap())?;
+        assert_eq!(first["params"]["bar"], "한글");
+        assert_eq!(second["id"], 1);
+        assert!(read_framed(&mut reader)?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use jni::sys::jint;
+use serde::Serialize;
+
+use crate::binding::{note_handler, NoteLibArgs, Response};
+use crate::path::{PathResolver, Paths};
+
+#[no_mangle]
+pub extern "system" fn Java_io_cjlee_gitnote_core_JniCoreConnector_add0<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    exec_path: JString<'local>,
+    file_path: JString<'local>,
+    line: jint,
+    message: JString<'local>,
+    content: JString<'local>,
+) -> JString<'local> {
+    let paths = paths(&mut env, &exec_path, &file_path);
+
+    let args = NoteLibArgs {
+        paths,
+        line: Some(line),
+        message: Some(peel_string(&mut env, &message)),
+        content: peel_nullable_string(&mut env, &content),
+    };
+
+    let response = Response::of(note_handler(&args.paths).and_then(|handler| handler.add_note(&args)).map(|_| String::new()));
+    new_json_string(env, &response)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_cjlee_gitnote_core_JniCoreConnector_read0<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    exec_path: JString<'local>,
+    file_path: JString<'local>,
+    content: JString<'local>,
+) -> JString<'local> {
+    let paths = paths(&mut env, &exec_path, &file_path);
+    let args = NoteLibArgs {
+        paths,
+        line: None,
+        message: None,
+        content: peel_nullable_string(&mut env, &content),
+    };
+    let response = Response::of(note_handler(&args.paths).and_then(|handler| {
+        let note = handler.read_note(&args)?.opaque_note()?;
+        Ok(serde_json::to_string(&note)?)
+    }));
+    new_json_string(env, &response)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_cjlee_gitnote_core_JniCoreConnector_update0<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    exec_path: JString<'local>,
+    file_path: JString<'local>,
+    line: jint,
+    message: JString<'local>,
+    content: JString<'local>,
+) -> JString<'local> {
+    let paths = paths(&mut env, &exec_path, &file_path);
+
+    let args = NoteLibArgs {
+        paths,
+        line: Some(line),
+        message: Some(peel_string(&mut env, &message)),
+        content: peel_nullable_string(&mut env, &content),
+    };
+
+    let response = Response::of(note_handler(&args.paths).and_then(|handler| handler.edit_note(&args)).map(|_| String::new()));
+    new_json_string(env, &response)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_cjlee_gitnote_core_JniCoreConnector_delete0<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    exec_path: JString<'local>,
+    file_path: JString<'local>,
+    line: jint,
+    content: JString<'local>,
+) -> JString<'local> {
+    let paths = paths(&mut env, &exec_path, &file_path);
+
+    let args = NoteLibArgs {
+        paths,
+        line: Some(line),
+        message: None,
+        content: peel_nullable_string(&mut env, &content),
+    };
+    let response = Response::of(note_handler(&args.paths).and_then(|handler| handler.delete_note(&args)).map(|_| String::new()));
+    new_json_string(env, &response)
+}
+
+fn paths(env: &mut JNIEnv, exec_path: &JString, file_path: &JString) -> Paths {
+    let exec_path = peel_string(env, exec_path);
+    let current_path = Path::new(&exec_path);
+    let file_path = &peel_string(env, file_path);
+    PathResolver::resolve(current_path, file_path).unwrap()
+}
+
+fn peel_string(env: &mut JNIEnv, jstring: &JString) -> String {
+    env.get_string(jstring).expect("Couldn't get java string").into()
+}
+
+/// `null` is passed when there is no unsaved content, which means the file on disk is up to date.
+fn peel_nullable_string(env: &mut JNIEnv, jstring: &JString) -> Option<String> {
+    if jstring.is_null() {
+        return None;
+    }
+    Some(peel_string(env, jstring))
+}
+
+fn new_json_string<'a, T>(env: JNIEnv<'a>, o: &T) -> JString<'a>
+where
+    T: ?Sized + Serialize,
+{
+    let str = serde_json::to_string(o).expect("Couldn't serialize response");
+    env.new_string(str).expect("Couldn't create java string").into()
+}
+
+fn new_jstring<'a>(env: JNIEnv<'a>, s: &str) -> JString<'a> {
+    env.new_string(s).expect("Couldn't create java string").into()
+}
@@ -1,20 +1,30 @@
-use std::fs;
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use configparser::ini::Ini;
 use flate2::Compression;
 use flate2::read::{ZlibDecoder, ZlibEncoder};
-use once_cell::sync::Lazy;
-use regex::Regex;
-
-use crate::config::CONFIG;
-use crate::diff::{Differ, DiffModel};
-use crate::path::Paths;
+use itertools::Itertools;
+
+use crate::attributes::{AttrValue, GitAttributes};
+use crate::config::{Charset, Config, LibgitBackend};
+use crate::diff::{Differ, SimilarDiffer};
+use crate::error::NoteError;
+use crate::note::Message;
+use crate::pack::{hex_to_bytes, PackedObjects};
+use crate::path::{Paths, SNAPSHOTS_REF};
 use crate::utils::{create_file_if_not_exists, PathBufExt};
 
+/// identity used for the commits which keep snapshot blobs reachable.
+const SNAPSHOTS_IDENT: &str = "git-note <git-note@localhost>";
+const SNAPSHOTS_MESSAGE: &str = "keep snapshot blobs of git-note reachable";
+
 #[derive(Debug)]
 pub struct GitBlob {
     pub id: String,
@@ -33,10 +43,161 @@ impl GitBlob {
     }
 }
 
+/// The commit which last changed a line, as `git blame` tells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    /// starts from 0.
+    pub line: usize,
+    pub commit: String,
+    pub author: String,
+    /// when the line was committed, rather than authored, e.g. a rebased commit is as new as the rebase.
+    pub time: DateTime<Utc>,
+}
+
+impl BlameLine {
+    /// A line which is not in `HEAD` yet, e.g. of an untracked file or of a repository without any commit.
+    /// It is the same as `git blame` tells for a line changed in the working tree.
+    pub fn not_committed(line: usize) -> Self {
+        BlameLine { line, commit: "0".repeat(40), author: "Not Committed Yet".to_string(), time: Utc::now() }
+    }
+
+    /// Whether the line is changed in the working tree, which is not committed yet.
+    pub fn is_committed(&self) -> bool {
+        self.commit.chars().any(|c| c != '0')
+    }
+
+    /// Whether the line was committed after the message was written, which may outdate the message.
+    /// Lines not committed yet are unknown, so that they are never taken as changed.
+    pub fn changed_after(&self, message: &Message) -> bool {
+        self.is_committed() && &self.time > message.created_at()
+    }
+}
+
+/// Reads the output of `git blame --porcelain`, where the details of a commit are given only on its first line.
+pub fn parse_blame(porcelain: &str) -> anyhow::Result<Vec<BlameLine>> {
+    let mut details: HashMap<String, (String, DateTime<Utc>)> = HashMap::new();
+    let mut blamed = Vec::new();
+    let mut current: Option<(String, usize)> = None;
+    for row in porcelain.lines() {
+        if row.starts_with('\t') {
+            let (commit, line) = current.take().ok_or(anyhow!("a line of blame has no commit"))?;
+            let (author, time) = details.get(&commit).cloned().unwrap_or_else(|| (String::new(), DateTime::UNIX_EPOCH));
+            blamed.push(BlameLine { line: line - 1, commit, author, time });
+            continue;
+        }
+        let (key, value) = row.split_once(' ').unwrap_or((row, ""));
+        let commit = current.as_ref().map(|(commit, _)| commit.clone()).unwrap_or_default();
+        match key {
+            "author" => details.entry(commit).or_default().0 = value.to_string(),
+            "committer-time" => {
+                let seconds = value.parse::<i64>()?;
+                details.entry(commit).or_default().1 = DateTime::from_timestamp(seconds, 0).unwrap_or_default();
+            }
+            // a commit of either SHA-1 or SHA-256
+            _ if matches!(key.len(), 40 | 64) && key.chars().all(|c| c.is_ascii_hexdigit()) => {
+                let line = value.split(' ').nth(1).ok_or(anyhow!("invalid header of blame : {}", row))?;
+                current = Some((key.to_string(), line.parse()?));
+            }
+            _ => {}
+        }
+    }
+    blamed.sort_by_key(|blamed| blamed.line);
+    Ok(blamed)
+}
+
 pub trait Libgit {
     fn make_git_blob(&self, paths: &Paths, persist: bool) -> anyhow::Result<GitBlob>;
-    fn read_git_blob(&self, paths: &Paths, oid: &String) -> anyhow::Result<GitBlob>;
-    fn diff(&self, old: &String, new: &String, diff_model: &mut DiffModel);
+    /// Same as [`Libgit::make_git_blob`], but with given content instead of the file on disk,
+    /// e.g. an unsaved buffer of editor. The content is encoded with the charset of the file.
+    fn make_git_blob_of(&self, paths: &Paths, content: &String, persist: bool) -> anyhow::Result<GitBlob>;
+    fn read_git_blob(&self, paths: &Paths, oid: &String) -> anyhow::Result<GitBlob> {
+        self.read_git_blob_as(paths, oid, charset_of(paths)?)
+    }
+    /// Same as [`Libgit::read_git_blob`], but decoded with given charset,
+    /// so that a caller reading many snapshots of a file resolves its charset only once.
+    fn read_git_blob_as(&self, paths: &Paths, oid: &String, charset: Charset) -> anyhow::Result<GitBlob>;
+    /// The differ which follows lines of a snapshot to the current content.
+    fn differ(&self) -> &dyn Differ;
+    /// Keeps given blobs reachable from a private ref, so that `git gc` never prunes them.
+    /// The ref is replaced on every call, which lets blobs no longer referenced by any note go.
+    fn retain_blobs(&self, paths: &Paths, oids: &Vec<String>) -> anyhow::Result<()>;
+    /// Blobs kept reachable by [`Libgit::retain_blobs`] so far, which is empty if nothing is retained yet.
+    fn retained_blobs(&self, paths: &Paths) -> anyhow::Result<Vec<String>>;
+    /// Tells whether the file is not a text, see [`is_binary`].
+    fn is_binary(&self, paths: &Paths) -> anyhow::Result<bool> {
+        is_binary(paths)
+    }
+
+    // Methods below walk the history, which run `git` by default, as neither [`ManualLibgit`] nor tests need to do it by hand.
+
+    /// Resolves a revision, e.g. `HEAD~1`, into the id of commit.
+    fn resolve_commit(&self, paths: &Paths, rev: &str) -> anyhow::Result<String> {
+        git_output(&paths.root(), vec!["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)], None)
+            .map_err(|_| anyhow!("unknown revision `{}`", rev))
+    }
+    /// Files under the directory of given paths which git doesn't ignore, tracked or not, relative to root of the repository.
+    /// A symlink to a directory is listed as it is, rather than followed.
+    fn listed_files(&self, paths: &Paths) -> anyhow::Result<Vec<PathBuf>> {
+        let relative = paths.relative();
+        let pathspec = if relative.as_os_str().is_empty() { "." } else { relative.try_to_str()? };
+        let output = execute_git(&paths.root(), vec!["ls-files", "-z", "--cached", "--others", "--exclude-standard", "--", pathspec], None)?;
+        // unmerged files are listed once per stage.
+        Ok(String::from_utf8_lossy(&output).split('\0').filter(|path| !path.is_empty()).dedup().map(PathBuf::from).collect())
+    }
+    /// Files changed by the commit from its first parent, relative to root of the repository.
+    fn changed_files(&self, paths: &Paths, commit: &String) -> anyhow::Result<Vec<PathBuf>> {
+        let output = execute_git(&paths.root(), vec!["diff-tree", "--no-commit-id", "--name-only", "-r", "--root", "-z", commit], None)?;
+        Ok(String::from_utf8_lossy(&output).split('\0').filter(|path| !path.is_empty()).map(PathBuf::from).collect())
+    }
+    /// Files which differ between two commits, where the index is taken if `old` is none, and the working tree if `new` is none,
+    /// just as `git diff` does.
+    fn diff_files(&self, paths: &Paths, old: Option<&String>, new: Option<&String>) -> anyhow::Result<Vec<PathBuf>> {
+        let mut args = vec!["diff", "--name-only", "--no-renames", "-z"];
+        args.extend(old.iter().chain(new.iter()).map(|rev| rev.as_str()));
+        let output = execute_git(&paths.root(), args, None)?;
+        Ok(String::from_utf8_lossy(&output).split('\0').filter(|path| !path.is_empty()).map(PathBuf::from).collect())
+    }
+    /// The file of given paths at the revision, which is none if the file doesn't exist there.
+    /// The index is taken if the revision is empty.
+    fn read_rev_blob(&self, paths: &Paths, rev: &String) -> anyhow::Result<Option<GitBlob>> {
+        let spec = format!("{}:{}", rev, paths.relative().try_to_str()?.replace('\\', "/"));
+        match git_output(&paths.root(), vec!["rev-parse", "--verify", "--quiet", &spec], None) {
+            Ok(oid) => Ok(Some(self.read_git_blob(paths, &oid)?)),
+            Err(_) => Ok(None),
+        }
+    }
+    /// Notes of git itself in `refs/notes/<notes_ref>`, paired with the commit each one is attached to.
+    fn read_git_notes(&self, paths: &Paths, notes_ref: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let root = paths.root();
+        let list = git_output(&root, vec!["notes", "--ref", notes_ref, "list"], None)?;
+        list.lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(note, commit)| Ok((commit.to_string(), git_output(&root, vec!["cat-file", "blob", note], None)?)))
+            .collect()
+    }
+    /// The commit which last changed each line of the file, where the lines are of `content` if given, e.g. an unsaved buffer of editor.
+    /// `git blame` fails on a file which is not in `HEAD`, so that every line of it is taken as not committed yet.
+    fn blame(&self, paths: &Paths, content: Option<&String>) -> anyhow::Result<Vec<BlameLine>> {
+        let relative = paths.relative();
+        let committed = format!("HEAD:{}", relative.try_to_str()?);
+        if execute_git(&paths.root(), vec!["cat-file", "-e", &committed], None).is_err() {
+            let content = match content {
+                Some(content) => content.clone(),
+                None => decode_content(fs::read(paths.canonical())?, paths, charset_of(paths)?)?,
+            };
+            return Ok(content.lines().enumerate().map(|(line, _)| BlameLine::not_committed(line)).collect());
+        }
+        let porcelain = match content {
+            Some(content) => execute_git(&paths.root(), vec!["blame", "--porcelain", "--contents", "-", "--", relative.try_to_str()?], Some(&encode_content(content, paths)?))?,
+            None => execute_git(&paths.root(), vec!["blame", "--porcelain", "--", relative.try_to_str()?], None)?,
+        };
+        parse_blame(&String::from_utf8_lossy(&porcelain))
+    }
+    /// Attaches a note of git itself to the commit in `refs/notes/<notes_ref>`, replacing the previous one.
+    fn write_git_note(&self, paths: &Paths, notes_ref: &str, commit: &String, content: &str) -> anyhow::Result<()> {
+        git_output(&paths.root(), vec!["notes", "--ref", notes_ref, "add", "--force", "--file", "-", commit], Some(content.as_bytes()))?;
+        Ok(())
+    }
 
     fn object_path(&self, paths: &Paths, oid: &String) -> PathBuf {
         paths.objects()
@@ -45,6 +206,180 @@ pub trait Libgit {
     }
 }
 
+impl<L> Libgit for Box<L>
+where
+    L: Libgit + ?Sized,
+{
+    fn make_git_blob(&self, paths: &Paths, persist: bool) -> anyhow::Result<GitBlob> {
+        (**self).make_git_blob(paths, persist)
+    }
+
+    fn make_git_blob_of(&self, paths: &Paths, content: &String, persist: bool) -> anyhow::Result<GitBlob> {
+        (**self).make_git_blob_of(paths, content, persist)
+    }
+
+    fn read_git_blob(&self, paths: &Paths, oid: &String) -> anyhow::Result<GitBlob> {
+        (**self).read_git_blob(paths, oid)
+    }
+
+    fn read_git_blob_as(&self, paths: &Paths, oid: &String, charset: Charset) -> anyhow::Result<GitBlob> {
+        (**self).read_git_blob_as(paths, oid, charset)
+    }
+
+    fn differ(&self) -> &dyn Differ {
+        (**self).differ()
+    }
+
+    fn retain_blobs(&self, paths: &Paths, oids: &Vec<String>) -> anyhow::Result<()> {
+        (**self).retain_blobs(paths, oids)
+    }
+
+    fn retained_blobs(&self, paths: &Paths) -> anyhow::Result<Vec<String>> {
+        (**self).retained_blobs(paths)
+    }
+
+    fn is_binary(&self, paths: &Paths) -> anyhow::Result<bool> {
+        (**self).is_binary(paths)
+    }
+
+    fn resolve_commit(&self, paths: &Paths, rev: &str) -> anyhow::Result<String> {
+        (**self).resolve_commit(paths, rev)
+    }
+
+    fn listed_files(&self, paths: &Paths) -> anyhow::Result<Vec<PathBuf>> {
+        (**self).listed_files(paths)
+    }
+
+    fn changed_files(&self, paths: &Paths, commit: &String) -> anyhow::Result<Vec<PathBuf>> {
+        (**self).changed_files(paths, commit)
+    }
+
+    fn diff_files(&self, paths: &Paths, old: Option<&String>, new: Option<&String>) -> anyhow::Result<Vec<PathBuf>> {
+        (**self).diff_files(paths, old, new)
+    }
+
+    fn read_rev_blob(&self, paths: &Paths, rev: &String) -> anyhow::Result<Option<GitBlob>> {
+        (**self).read_rev_blob(paths, rev)
+    }
+
+    fn read_git_notes(&self, paths: &Paths, notes_ref: &str) -> anyhow::Result<Vec<(String, String)>> {
+        (**self).read_git_notes(paths, notes_ref)
+    }
+
+    fn blame(&self, paths: &Paths, content: Option<&String>) -> anyhow::Result<Vec<BlameLine>> {
+        (**self).blame(paths, content)
+    }
+
+    fn write_git_note(&self, paths: &Paths, notes_ref: &str, commit: &String, content: &str) -> anyhow::Result<()> {
+        (**self).write_git_note(paths, notes_ref, commit, content)
+    }
+}
+
+/// Runs git and returns its stdout untouched, which matters when the output is a file content.
+/// Commits made by git are of the identity of git-note, as they are not of the user.
+fn execute_git(path: &Path, args: Vec<&str>, input: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    let mut child = Command::new("git")
+        .args(args.clone())
+        .current_dir(path)
+        .env("GIT_AUTHOR_NAME", "git-note")
+        .env("GIT_AUTHOR_EMAIL", "git-note@localhost")
+        .env("GIT_COMMITTER_NAME", "git-note")
+        .env("GIT_COMMITTER_EMAIL", "git-note@localhost")
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("!Failed to run `git {:?}`", args))?;
+    if let Some(input) = input {
+        child.stdin.take()
+            .ok_or(anyhow!("Failed to open stdin of `git {:?}`", args))?
+            .write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Failed to run `git {args:?}`, error : {}", stderr.trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// Same as [`execute_git`], but trims the output as a text.
+fn git_output(path: &Path, args: Vec<&str>, input: Option<&[u8]>) -> anyhow::Result<String> {
+    let stdout = execute_git(path, args, input)?;
+    Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+}
+
+/// Creates the [`Libgit`] implementation of given backend.
+pub fn libgit_of(backend: LibgitBackend) -> Box<dyn Libgit> {
+    match backend {
+        LibgitBackend::Manual => Box::new(ManualLibgit::new(SimilarDiffer)),
+        LibgitBackend::Process => Box::new(ProcessLibgit::new(SimilarDiffer)),
+    }
+}
+
+/// Both backends decode the content of a file exactly as it is stored, with the charset of the file.
+fn decode_content(content: Vec<u8>, paths: &Paths, charset: Charset) -> anyhow::Result<String> {
+    charset.decode(&content)
+        .map_err(|e| anyhow!("Failed to decode file content from `{}` with charset `{}` : {}", paths.canonical().display(), charset, e))
+}
+
+fn encode_content(content: &String, paths: &Paths) -> anyhow::Result<Vec<u8>> {
+    let charset = charset_of(paths)?;
+    charset.encode(content)
+        .map_err(|e| anyhow!("Failed to encode content of `{}` with charset `{}` : {}", paths.canonical().display(), charset, e))
+}
+
+/// Resolves the charset of the file, preferring `working-tree-encoding` in `.gitattributes`
+/// over `charset` in config.
+pub fn charset_of(paths: &Paths) -> anyhow::Result<Charset> {
+    let attributes = GitAttributes::load(paths)?;
+    if let Some(AttrValue::Value(label)) = attributes.get(&paths.relative(), "working-tree-encoding") {
+        return Charset::for_label(&label);
+    }
+    Ok(*Config::load(paths)?.charset())
+}
+
+/// Tells whether the file is not a text, by `binary` or `-diff` in `.gitattributes` if specified,
+/// otherwise by the same heuristic as git, which looks for a NUL byte in the first 8000 bytes.
+pub fn is_binary(paths: &Paths) -> anyhow::Result<bool> {
+    let attributes = GitAttributes::load(paths)?;
+    match attributes.get(&paths.relative(), "diff") {
+        Some(AttrValue::Unset) => return Ok(true),
+        Some(AttrValue::Set) => return Ok(false),
+        _ => {}
+    }
+    let mut head = Vec::new();
+    fs::File::open(paths.canonical())
+        .context(format!("Failed to read file at path: {:?}", paths.canonical()))?
+        .take(8000)
+        .read_to_end(&mut head)?;
+    Ok(head.contains(&0))
+}
+
+/// Resolves who writes a note as `name <email>`, the same way as git does for the author of a commit,
+/// from `GIT_AUTHOR_*` environment variables, the config of the repository, and then the global config.
+/// Returns `None` if no name is configured.
+pub fn author_of(paths: &Paths) -> Option<String> {
+    let mut configs = vec![paths.root().join(".git/config")];
+    if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        configs.push(PathBuf::from(home).join(".gitconfig"));
+    }
+    let lookup = |env: &str, key: &str| {
+        std::env::var(env).ok().or_else(|| {
+            configs.iter().find_map(|config| {
+                let mut ini = Ini::new();
+                ini.load(config).ok()?;
+                ini.get("user", key)
+            })
+        })
+    };
+    let name = lookup("GIT_AUTHOR_NAME", "name")?;
+    return match lookup("GIT_AUTHOR_EMAIL", "email") {
+        Some(email) => Some(format!("{} <{}>", name, email)),
+        None => Some(name),
+    };
+}
+
 pub struct ProcessLibgit<T>
 where
     T: Differ,
@@ -60,30 +395,32 @@ where
         Self { differ }
     }
 
-    fn read_file_content(&self, paths: &Paths) -> anyhow::Result<String> {
-        let bytes = fs::read(paths.canonical())?;
-        self.decode(&bytes)
-            .map_err(|e| anyhow!("Failed to decode file content from `{}` : {}", paths.canonical().display(), e))
+    fn execute_git_command(&self, path: &Path, args: Vec<&str>) -> anyhow::Result<String> {
+        let stdout = self.execute_git_command_raw(path, args, None)?;
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
     }
 
-    fn decode(&self, bytes: &[u8]) -> anyhow::Result<String> {
-        let charset = CONFIG.charset();
-        charset.decode(bytes)
-            .map_err(|e| anyhow!("Failed to decode file with given charset `{}` : {}", charset, e))
+    fn execute_git_command_with_input(&self, path: &Path, args: Vec<&str>, input: &str) -> anyhow::Result<String> {
+        let stdout = self.execute_git_command_raw(path, args, Some(input.as_bytes()))?;
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
     }
 
-    fn execute_git_command(&self, path: &Path, args: Vec<&str>) -> anyhow::Result<String> {
-        let output = Command::new("git")
-            .args(args.clone())
-            .current_dir(path)
-            .output()
-            .context(format!("!Failed to run `git {:?}`", args))?;
-        if !output.status.success() {
-            let stderr = self.decode(&output.stderr)?;
-            return Err(anyhow!("Failed to run `git {args:?}`, error : {stderr}"));
-        }
-        let stdout = self.decode(&output.stdout)?;
-        Ok(stdout.trim().to_string())
+    /// Runs git and returns its stdout untouched, which matters when the output is a file content.
+    fn execute_git_command_raw(&self, path: &Path, args: Vec<&str>, input: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+        execute_git(path, args, input)
+    }
+
+    /// Given objects which exist in the repository, checked by a single `git cat-file --batch-check`.
+    fn existing_objects<'o>(&self, paths: &Paths, oids: &[&'o String]) -> anyhow::Result<Vec<&'o String>> {
+        let input = oids.iter().map(|oid| format!("{oid}\n")).join("");
+        let output = self.execute_git_command_raw(&paths.root(), vec!["cat-file", "--batch-check"], Some(input.as_bytes()))?;
+        // a line per oid in order, which is `<oid> missing` if the object doesn't exist.
+        let existing = oids.iter()
+            .zip(String::from_utf8_lossy(&output).lines())
+            .filter(|(_, line)| !line.ends_with(" missing"))
+            .map(|(oid, _)| *oid)
+            .collect();
+        Ok(existing)
     }
 }
 
@@ -92,29 +429,76 @@ where
     T: Differ,
 {
     fn make_git_blob(&self, paths: &Paths, persist: bool) -> anyhow::Result<GitBlob> {
-        let id = self.execute_git_command(
-            &paths.root(),
-            vec!["hash-object", "-w", paths.relative().try_to_str()?],
-        )?;
-        let content = self.read_file_content(paths)?;
+        // `--no-filters` hashes the file as-is, just like `ManualLibgit` does.
+        let mut args = vec!["hash-object", "--no-filters"];
+        if persist {
+            args.push("-w");
+        }
+        let relative = paths.relative();
+        args.push(relative.try_to_str()?);
+        let id = self.execute_git_command(&paths.root(), args)?;
+
+        let content = fs::read(paths.canonical())
+            .context(format!("Failed to read file at path: {:?}", paths.canonical()))?;
         Ok(GitBlob {
             id,
-            file_path: paths.relative().clone(),
-            content: content.replace("\r\n", "\n"),
+            file_path: paths.relative(),
+            content: decode_content(content, paths, charset_of(paths)?)?,
         })
     }
 
-    fn read_git_blob(&self, paths: &Paths, oid: &String) -> anyhow::Result<GitBlob> {
-        let content = self.execute_git_command(&paths.root(), vec!["cat-file", "-p", oid])?;
+    fn make_git_blob_of(&self, paths: &Paths, content: &String, persist: bool) -> anyhow::Result<GitBlob> {
+        let mut args = vec!["hash-object", "--no-filters", "--stdin"];
+        if persist {
+            args.push("-w");
+        }
+        let encoded = encode_content(content, paths)?;
+        let id = self.execute_git_command_raw(&paths.root(), args, Some(&encoded))?;
+        Ok(GitBlob {
+            id: String::from_utf8_lossy(&id).trim().to_string(),
+            file_path: paths.relative(),
+            content: content.clone(),
+        })
+    }
+
+    fn read_git_blob_as(&self, paths: &Paths, oid: &String, charset: Charset) -> anyhow::Result<GitBlob> {
+        let content = self.execute_git_command_raw(&paths.root(), vec!["cat-file", "blob", oid], None)?;
         Ok(GitBlob {
             id: oid.clone(),
-            file_path: paths.relative().clone(),
-            content: content.replace("\r\n", "\n"),
+            file_path: paths.relative(),
+            content: decode_content(content, paths, charset)?,
         })
     }
 
-    fn diff(&self, old: &String, new: &String, diff_model: &mut DiffModel) {
-        self.differ.diff(old, new, diff_model);
+    fn differ(&self) -> &dyn Differ {
+        &self.differ
+    }
+
+    fn retain_blobs(&self, paths: &Paths, oids: &Vec<String>) -> anyhow::Result<()> {
+        let root = paths.root();
+        // messages on the whole file have no snapshot.
+        let oids: Vec<&String> = oids.iter().filter(|oid| !oid.is_empty()).sorted().dedup().collect();
+        let tree_input = self.existing_objects(paths, &oids)?
+            .iter()
+            .map(|oid| format!("100644 blob {oid}\t{oid}\n"))
+            .join("");
+        let tree = self.execute_git_command_with_input(&root, vec!["mktree"], &tree_input)?;
+        let current = self.execute_git_command(&root, vec!["rev-parse", "--quiet", "--verify", &format!("{SNAPSHOTS_REF}^{{tree}}")]);
+        if current.is_ok_and(|current| current == tree) {
+            return Ok(());
+        }
+        let commit = self.execute_git_command_with_input(&root, vec!["commit-tree", &tree], SNAPSHOTS_MESSAGE)?;
+        self.execute_git_command(&root, vec!["update-ref", SNAPSHOTS_REF, &commit])?;
+        Ok(())
+    }
+
+    fn retained_blobs(&self, paths: &Paths) -> anyhow::Result<Vec<String>> {
+        let root = paths.root();
+        if self.execute_git_command(&root, vec!["rev-parse", "--quiet", "--verify", SNAPSHOTS_REF]).is_err() {
+            return Ok(Vec::new());
+        }
+        let names = self.execute_git_command(&root, vec!["ls-tree", "--name-only", SNAPSHOTS_REF])?;
+        Ok(names.lines().map(String::from).collect())
     }
 }
 
@@ -133,10 +517,9 @@ where
         Self { differ }
     }
 
-    fn make_blob_bytes(&self, content: &Vec<u8>) -> Vec<u8> {
-        let prefix = "blob ";
+    fn make_object_bytes(&self, kind: &str, content: &[u8]) -> Vec<u8> {
         let len = content.len();
-        let mut target = format!("{}{}\0", prefix, len).as_bytes().to_vec();
+        let mut target = format!("{} {}\0", kind, len).as_bytes().to_vec();
         target.extend(content.iter());
         target
     }
@@ -161,6 +544,108 @@ where
         create_file_if_not_exists(&object_dir_path, object_file_path, Some(encoded))?;
         Ok(())
     }
+
+    fn write_object(&self, paths: &Paths, kind: &str, content: &[u8]) -> anyhow::Result<String> {
+        let object = self.make_object_bytes(kind, content);
+        let oid = self.oid(&object);
+        let encoded = self.zlib_encode(object)?;
+        self.save_blob(paths.objects(), &oid, encoded)?;
+        Ok(oid)
+    }
+
+    /// Computes the id of a blob with given content, and writes it only if `persist` is true.
+    fn hash_blob(&self, paths: &Paths, content: &[u8], persist: bool) -> anyhow::Result<String> {
+        let blob = self.make_object_bytes("blob", content);
+        let oid = self.oid(&blob);
+        if persist {
+            let encoded = self.zlib_encode(blob)?;
+            self.save_blob(paths.objects(), &oid, encoded)?;
+        }
+        Ok(oid)
+    }
+
+    /// Reads an object either from its loose file or from packs, returning its type and content.
+    fn read_object(&self, paths: &Paths, oid: &String) -> anyhow::Result<(String, Vec<u8>)> {
+        let object_path = self.object_path(paths, oid);
+        if !object_path.exists() {
+            return PackedObjects::open(&paths.objects())?
+                .read(oid)?
+                .ok_or(anyhow!("cannot find object `{}` in {:?}", oid, paths.objects()));
+        }
+        let bytes = fs::read(object_path)?;
+        let mut decoder = ZlibDecoder::new(&bytes[..]);
+        let mut object = Vec::new();
+        decoder.read_to_end(&mut object)?;
+
+        let nul = object.iter()
+            .position(|b| *b == 0)
+            .ok_or(anyhow!("malformed object `{}`", oid))?;
+        let header = String::from_utf8_lossy(&object[..nul]).to_string();
+        let kind = header.split(' ').next().unwrap_or_default().to_string();
+        Ok((kind, object[nul + 1..].to_vec()))
+    }
+
+    fn has_object(&self, paths: &Paths, packs: &PackedObjects, oid: &String) -> bool {
+        oid.len() == 40 && (self.object_path(paths, oid).exists() || packs.contains(oid))
+    }
+
+    /// The commit of the private ref of snapshots, which is either a loose file or in `packed-refs` after `git gc`.
+    fn snapshots_commit(&self, paths: &Paths) -> anyhow::Result<Option<String>> {
+        match fs::read_to_string(paths.snapshots()) {
+            Ok(commit) => return Ok(Some(commit.trim().to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("Failed to read ref at path: {:?}", paths.snapshots())),
+        }
+        let packed_refs = paths.root().join(".git/packed-refs");
+        let packed = match fs::read_to_string(&packed_refs) {
+            Ok(packed) => packed,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(format!("Failed to read refs at path: {:?}", packed_refs)),
+        };
+        // lines are `<oid> <ref>`, besides the header starting with `#` and peeled tags starting with `^`.
+        let commit = packed.lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, name)| *name == SNAPSHOTS_REF)
+            .map(|(oid, _)| oid.to_string());
+        Ok(commit)
+    }
+
+    fn snapshots_tree(&self, paths: &Paths) -> anyhow::Result<Option<String>> {
+        let Some(commit) = self.snapshots_commit(paths)? else {
+            return Ok(None);
+        };
+        let (kind, content) = self.read_object(paths, &commit)?;
+        if kind != "commit" {
+            return Err(anyhow!("`{}` is not a commit but a {}", SNAPSHOTS_REF, kind));
+        }
+        let tree = String::from_utf8(content)?
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("tree "))
+            .map(String::from)
+            .ok_or(anyhow!("malformed commit `{}`", commit))?;
+        Ok(Some(tree))
+    }
+
+    /// Points the private ref of snapshots to the commit, replacing it atomically through `<ref>.lock` as git does.
+    fn update_snapshots(&self, paths: &Paths, commit: &str) -> anyhow::Result<()> {
+        let snapshots = paths.snapshots();
+        let parent = snapshots.parent().ok_or(anyhow!("invalid ref path {:?}", snapshots))?;
+        fs::create_dir_all(parent)?;
+        let lock = snapshots.with_extension("lock");
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(NoteError::Busy(lock).into()),
+            Err(e) => return Err(e).context(format!("Failed to lock ref at path: {:?}", lock)),
+        };
+        let written = file.write_all(format!("{commit}\n").as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&lock, &snapshots));
+        if written.is_err() {
+            let _ = fs::remove_file(&lock);
+        }
+        written.context(format!("Failed to update ref at path: {:?}", snapshots))
+    }
 }
 
 impl<T> Libgit for ManualLibgit<T>
@@ -170,49 +655,95 @@ where
     fn make_git_blob(&self, paths: &Paths, persist: bool) -> anyhow::Result<GitBlob> {
         let content = fs::read(paths.canonical())
             .context(format!("Failed to read file at path: {:?}", paths.canonical()))?;
-        let blob = self.make_blob_bytes(&content);
-
-        let oid = self.oid(&blob);
-        if persist {
-            let encoded = self.zlib_encode(blob)?;
-            self.save_blob(paths.objects(), &oid, encoded)?;
-        }
+        let oid = self.hash_blob(paths, &content, persist)?;
 
         let blob = GitBlob {
             id: oid,
             file_path: paths.relative(),
-            content: String::from_utf8(content)?,
+            content: decode_content(content, paths, charset_of(paths)?)?,
         };
         Ok(blob)
     }
 
-    fn read_git_blob(&self, paths: &Paths, oid: &String) -> anyhow::Result<GitBlob> {
-        static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"blob \d+\x00").unwrap());
+    fn make_git_blob_of(&self, paths: &Paths, content: &String, persist: bool) -> anyhow::Result<GitBlob> {
+        let encoded = encode_content(content, paths)?;
+        let oid = self.hash_blob(paths, &encoded, persist)?;
 
-        let blob_path = self.object_path(paths, oid);
-        let bytes = fs::read(blob_path)?;
-        let mut decoder = ZlibDecoder::new(&bytes[..]);
-        let mut blob = String::new();
-        decoder.read_to_string(&mut blob)?;
-        let content = RE.replacen(&blob, 1, "");
+        let blob = GitBlob {
+            id: oid,
+            file_path: paths.relative(),
+            content: content.clone(),
+        };
+        Ok(blob)
+    }
+
+    fn read_git_blob_as(&self, paths: &Paths, oid: &String, charset: Charset) -> anyhow::Result<GitBlob> {
+        let (kind, content) = self.read_object(paths, oid)?;
+        if kind != "blob" {
+            return Err(anyhow!("object `{}` is not a blob but a {}", oid, kind));
+        }
 
         let git_blob = GitBlob {
             id: oid.clone(),
-            file_path: paths.relative().clone(),
-            content: content.to_string(),
+            file_path: paths.relative(),
+            content: decode_content(content, paths, charset)?,
         };
         Ok(git_blob)
     }
 
-    fn diff(&self, old: &String, new: &String, diff_model: &mut DiffModel) {
-        self.differ.diff(old, new, diff_model);
+    fn differ(&self) -> &dyn Differ {
+        &self.differ
+    }
+
+    fn retain_blobs(&self, paths: &Paths, oids: &Vec<String>) -> anyhow::Result<()> {
+        let packs = PackedObjects::open(&paths.objects())?;
+        let mut tree = Vec::new();
+        for oid in oids.iter().filter(|oid| self.has_object(paths, &packs, oid)).sorted().dedup() {
+            // tree entries are sorted by name, and the oid itself is used as the name.
+            tree.extend(format!("100644 {}\0", oid).as_bytes());
+            tree.extend(hex_to_bytes(oid)?);
+        }
+        let tree_oid = self.write_object(paths, "tree", &tree)?;
+        if self.snapshots_tree(paths)?.is_some_and(|current| current == tree_oid) {
+            return Ok(());
+        }
+
+        let timestamp = Utc::now().timestamp();
+        let commit = format!(
+            "tree {tree_oid}\nauthor {SNAPSHOTS_IDENT} {timestamp} +0000\ncommitter {SNAPSHOTS_IDENT} {timestamp} +0000\n\n{SNAPSHOTS_MESSAGE}\n"
+        );
+        let commit_oid = self.write_object(paths, "commit", commit.as_bytes())?;
+        self.update_snapshots(paths, &commit_oid)
+    }
+
+    fn retained_blobs(&self, paths: &Paths) -> anyhow::Result<Vec<String>> {
+        let Some(tree_oid) = self.snapshots_tree(paths)? else {
+            return Ok(Vec::new());
+        };
+        let (_, tree) = self.read_object(paths, &tree_oid)?;
+        let mut oids = Vec::new();
+        let mut rest = &tree[..];
+        // entries are `<mode> <name>\0<oid in bytes>`, where the name is the oid in hex.
+        while let Some(nul) = rest.iter().position(|b| *b == 0) {
+            let entry = String::from_utf8_lossy(&rest[..nul]).to_string();
+            let name = entry.split_once(' ').map(|(_, name)| name.to_string())
+                .ok_or(anyhow!("malformed tree `{}`", tree_oid))?;
+            rest = rest.get(nul + 1 + name.len() / 2..).ok_or(anyhow!("malformed tree `{}`", tree_oid))?;
+            oids.push(name);
+        }
+        Ok(oids)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
+    use chrono::DateTime;
+
     use crate::diff::SimilarDiffer;
-    use crate::libgit::{Libgit, ManualLibgit};
+    use crate::libgit::{is_binary, parse_blame, BlameLine, Libgit, ManualLibgit, ProcessLibgit};
+    use crate::note::{Message, Scope};
     use crate::path::PathResolver;
     use crate::testlib::TestRepo;
 
@@ -247,4 +778,181 @@ mod tests {
         assert_eq!(blob.content, "  hello world\nmore lines\n   multiple spaces: and 한글");
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_backends_agree() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("  hello world\r\nmore lines\n"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let manual = ManualLibgit::new(SimilarDiffer);
+        let process = ProcessLibgit::new(SimilarDiffer);
+
+        // when
+        let manual_blob = manual.make_git_blob(&paths, true)?;
+        let process_blob = process.make_git_blob(&paths, false)?;
+
+        // then
+        assert_eq!(manual_blob.id, process_blob.id);
+        assert_eq!(manual_blob.content, process_blob.content);
+        let read_blob = process.read_git_blob(&paths, &manual_blob.id)?;
+        assert_eq!(read_blob.content, "  hello world\r\nmore lines\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_charset() -> anyhow::Result<()> {
+        // given : "hello는 안녕" in euc-kr
+        let bytes = [0x68, 0x65, 0x6c, 0x6c, 0x6f, 0xB4, 0xC2, 0x20, 0xBE, 0xC8, 0xB3, 0xE7];
+        let repo = TestRepo::new();
+        repo.create_file(".gitattributes", Some("*.kr working-tree-encoding=euc-kr"))?;
+        std::fs::write(repo.path().join("test.kr"), bytes)?;
+        let paths = PathResolver::resolve(repo.path(), "test.kr")?;
+
+        for libgit in [Box::new(ManualLibgit::new(SimilarDiffer)) as Box<dyn Libgit>, Box::new(ProcessLibgit::new(SimilarDiffer))] {
+            // when
+            let made = libgit.make_git_blob(&paths, true)?;
+            let read = libgit.read_git_blob(&paths, &made.id)?;
+            let buffered = libgit.make_git_blob_of(&paths, &"hello는 안녕".to_string(), false)?;
+
+            // then
+            assert_eq!(made.content, "hello는 안녕");
+            assert_eq!(read.content, "hello는 안녕");
+            assert_eq!(buffered.id, made.id);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_charset_from_config() -> anyhow::Result<()> {
+        // given
+        let bytes = [0xB4, 0xC2];
+        let repo = TestRepo::new();
+        std::fs::write(repo.path().join("test.txt"), bytes)?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let libgit = ManualLibgit::new(SimilarDiffer);
+        assert!(libgit.make_git_blob(&paths, false).is_err());
+
+        // when
+        std::fs::create_dir_all(paths.home())?;
+        std::fs::write(paths.config(), "charset: euc-kr")?;
+        let blob = libgit.make_git_blob(&paths, false)?;
+
+        // then
+        assert_eq!(blob.content, "는");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_binary() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("text.txt", Some("hello"))?;
+        repo.create_file("nul.dat", Some("he\0llo"))?;
+        repo.create_file("image.png", Some("not really an image"))?;
+        repo.create_file(".gitattributes", Some("*.png binary"))?;
+
+        // then
+        assert!(!is_binary(&PathResolver::resolve(repo.path(), "text.txt")?)?);
+        assert!(is_binary(&PathResolver::resolve(repo.path(), "nul.dat")?)?);
+        assert!(is_binary(&PathResolver::resolve(repo.path(), "image.png")?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blame() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\n"))?;
+        let commit = repo.commit("first")?;
+        repo.create_file("test.txt", Some("foo\nbar\nbaz\n"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let libgit = ManualLibgit::new(SimilarDiffer);
+
+        // when
+        let blamed = libgit.blame(&paths, None)?;
+        let buffer = libgit.blame(&paths, Some(&"new\nfoo\nbar\nbaz\n".to_string()))?;
+
+        // then
+        assert_eq!(blamed.iter().map(|b| b.line).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!((blamed[1].commit.as_str(), blamed[1].author.as_str()), (commit.as_str(), "tester"));
+        assert!(blamed[1].is_committed() && !blamed[2].is_committed());
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer[2].commit, commit);
+
+        // a message written after the commit
+        let message = Message::whole(Scope::File, "hello".to_string());
+        assert!(!blamed[0].changed_after(&message));
+        Ok(())
+    }
+
+    #[test]
+    fn diff_files_of_non_ascii() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("café.txt", Some("foo"))?;
+        repo.commit("first")?;
+        repo.create_file("café.txt", Some("bar"))?;
+        let paths = PathResolver::resolve(repo.path(), ".")?;
+
+        // when
+        let changed = ManualLibgit::new(SimilarDiffer).diff_files(&paths, Some(&"HEAD".to_string()), None)?;
+
+        // then
+        assert_eq!(changed, vec![PathBuf::from("café.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn changed_files_of_non_ascii() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("café.txt", Some("foo"))?;
+        repo.create_file("a b.txt", Some("bar"))?;
+        let commit = repo.commit("first")?;
+        let paths = PathResolver::resolve(repo.path(), ".")?;
+
+        // when
+        let changed = ManualLibgit::new(SimilarDiffer).changed_files(&paths, &commit)?;
+
+        // then
+        assert_eq!(changed, vec![PathBuf::from("a b.txt"), PathBuf::from("café.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn blame_not_committed() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\n"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let libgit = ManualLibgit::new(SimilarDiffer);
+
+        // when
+        let without_commit = libgit.blame(&paths, None)?;
+        repo.create_file("other.txt", Some("baz"))?;
+        repo.commit("first")?;
+        repo.create_file("untracked.txt", Some("qux"))?;
+        let untracked = libgit.blame(&PathResolver::resolve(repo.path(), "untracked.txt")?, None)?;
+
+        // then
+        assert_eq!(without_commit.len(), 2);
+        assert!(without_commit.iter().all(|b| !b.is_committed()));
+        assert_eq!(untracked, vec![BlameLine { time: untracked[0].time, ..BlameLine::not_committed(0) }]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_blame_of_sha256() -> anyhow::Result<()> {
+        // given
+        let commit = "a".repeat(64);
+        let porcelain = format!("{} 1 1 1\nauthor Jane\nauthor-time 100\ncommitter-time 200\n\tfoo\n", commit);
+
+        // when
+        let blamed = parse_blame(&porcelain)?;
+
+        // then
+        assert_eq!(blamed, vec![BlameLine { line: 0, commit, author: "Jane".to_string(), time: DateTime::from_timestamp(200, 0).unwrap() }]);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Advisory locks of note files, in the spirit of git's `.lock` files.
+//!
+//! A note is read, modified and written back as a whole, so two writers at once, e.g. IDE and terminal,
+//! would silently drop one of the writes. A writer creates `<note>.lock` exclusively before reading the note,
+//! and removes it after writing. Readers don't take the lock, as every write replaces the note atomically.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::NoteError;
+
+/// How long a writer waits for another one, which usually holds the lock only for a few milliseconds.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(3);
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Held while a note is read, modified and written. Released on drop.
+#[derive(Debug)]
+pub struct NoteLock {
+    path: PathBuf,
+}
+
+impl NoteLock {
+    pub fn acquire(note_path: &Path) -> anyhow::Result<Self> {
+        return Self::acquire_within(note_path, LOCK_TIMEOUT);
+    }
+
+    pub fn acquire_within(note_path: &Path, timeout: Duration) -> anyhow::Result<Self> {
+        let mut path = note_path.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // only to tell who holds it, when it is left by a crash.
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(NoteLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if started.elapsed() >= timeout {
+                        return Err(NoteError::Busy(path).into());
+                    }
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for NoteLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::diff::SimilarDiffer;
+    use crate::error::NoteError;
+    use crate::handlers::NoteHandler;
+    use crate::libgit::{Libgit, ManualLibgit};
+    use crate::lock::NoteLock;
+    use crate::note::Note;
+    use crate::path::PathResolver;
+    use crate::repository::NoteRepository;
+    use crate::testlib::{TestArgs, TestRepo};
+
+    #[test]
+    fn busy() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let note_path = repo.path().join("note");
+        let lock = NoteLock::acquire(&note_path)?;
+
+        // when
+        let busy = NoteLock::acquire_within(&note_path, Duration::from_millis(50));
+        drop(lock);
+        let released = NoteLock::acquire_within(&note_path, Duration::from_millis(50));
+
+        // then
+        let error = busy.unwrap_err();
+        assert!(matches!(error.downcast_ref::<NoteError>(), Some(NoteError::Busy(_))));
+        assert!(released.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let content = (1..=8).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        repo.create_file("test.txt", Some(&content))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+
+        // when
+        thread::scope(|scope| {
+            for line in 1..=8 {
+                let args = TestArgs::line(&paths, line, &format!("note on {}", line));
+                scope.spawn(move || {
+                    let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+                    handler.add_note(&args).unwrap();
+                });
+            }
+        });
+
+        // then
+        let note_path = paths.note(&Note::get_id(&paths.relative())?)?;
+        let note = repo.read_note(&note_path)?;
+        assert_eq!(note.messages.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers_and_deleters() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let content = (1..=8).map(|i| format!("line {}", i)).collect::<Vec<_>>().join("\n");
+        repo.create_file("test.txt", Some(&content))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+
+        // when : the note is emptied and written again over and over
+        thread::scope(|scope| {
+            for line in 1..=8 {
+                let args = TestArgs::line(&paths, line, &format!("note on {}", line));
+                scope.spawn(move || {
+                    let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+                    for _ in 0..4 {
+                        handler.add_note(&args).unwrap();
+                        handler.edit_note(&args).unwrap();
+                        handler.delete_note(&args).unwrap();
+                    }
+                    handler.add_note(&args).unwrap();
+                });
+            }
+        });
+
+        // then
+        let note_path = paths.note(&Note::get_id(&paths.relative())?)?;
+        let note = repo.read_note(&note_path)?;
+        assert_eq!(note.messages.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers_of_different_notes() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let files = (1..=8).map(|i| format!("test{}.txt", i)).collect::<Vec<_>>();
+        for file in &files {
+            repo.create_file(file, Some(file))?;
+        }
+
+        // when
+        thread::scope(|scope| {
+            for file in &files {
+                let paths = PathResolver::resolve(repo.path(), file).unwrap();
+                scope.spawn(move || {
+                    let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+                    handler.add_note(&TestArgs::line(&paths, 1, "hello")).unwrap();
+                });
+            }
+        });
+
+        // then : every snapshot is retained
+        let paths = PathResolver::resolve(repo.path(), &files[0])?;
+        assert_eq!(ManualLibgit::new(SimilarDiffer).retained_blobs(&paths)?.len(), 8);
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::handlers::{NoteArgs, NoteHandler};
+use crate::jsonrpc::{Incoming, INTERNAL_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND, Notification, PARSE_ERROR, read_framed, Response, RpcError, write_framed};
+use crate::libgit::{is_binary, Libgit};
+use crate::lsp::protocol::{CodeAction, CodeActionParams, Command, Diagnostic, DidChangeTextDocumentParams, DidOpenTextDocumentParams, ExecuteCommandParams, NoteCommandArgs, Position, Range, TextDocumentParams, TextDocumentPositionParams, uri_to_path};
+use crate::note::{Message, Scope};
+use crate::path::{PathResolver, Paths};
+
+pub mod protocol;
+
+const ADD_COMMAND: &str = "gitnote.add";
+const EDIT_COMMAND: &str = "gitnote.edit";
+const DELETE_COMMAND: &str = "gitnote.delete";
+
+/// severity of diagnostics, which is `Information`.
+const INFORMATION: u8 = 3;
+
+/// type of `window/logMessage`, which is `Error`.
+const ERROR: u8 = 1;
+
+/// A language server which speaks LSP over given reader and writer, usually stdio.
+///
+/// Notes are published as diagnostics when a document is opened, changed or saved, and shown on hover.
+/// Lines are anchored to the text synced from client, so they follow edits which are not saved yet.
+/// Adding, editing and deleting are offered as code actions, which run `gitnote.*` commands.
+/// As LSP has no way to prompt a text, clients should fill `message` argument of add and edit commands.
+pub struct LspServer<T>
+where
+    T: Libgit,
+{
+    note_handler: NoteHandler<T>,
+    /// text of opened documents by uri, which may differ from the file on disk until saved.
+    buffers: RefCell<HashMap<String, String>>,
+}
+
+impl<T> LspServer<T>
+where
+    T: Libgit,
+{
+    pub fn new(note_handler: NoteHandler<T>) -> Self {
+        Self {
+            note_handler,
+            buffers: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn run<R, W>(&self, reader: &mut R, writer: &mut W) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        while let Some(body) = read_framed(reader)? {
+            let incoming: Incoming = match serde_json::from_str(&body) {
+                Ok(incoming) => incoming,
+                Err(e) => {
+                    let error = RpcError::new(PARSE_ERROR, e.to_string());
+                    write_framed(writer, &Response::err(Value::Null, error))?;
+                    continue;
+                }
+            };
+            let Some(method) = incoming.method else {
+                continue; // a response, but the server never sends a request
+            };
+            match incoming.id {
+                Some(id) => {
+                    let response = match self.handle_request(&method, incoming.params, writer) {
+                        Ok(result) => Response::ok(id, result),
+                        Err(error) => Response::err(id, error),
+                    };
+                    write_framed(writer, &response)?;
+                }
+                None if method == "exit" => return Ok(()),
+                None => {
+                    // a notification has no response, so that its failure is only told to the user.
+                    if let Err(e) = self.handle_notification(&method, incoming.params, writer) {
+                        let params = json!({ "type": ERROR, "message": format!("git-note: {}", e) });
+                        write_framed(writer, &Notification::new("window/logMessage", params))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request<W>(&self, method: &str, params: Value, writer: &mut W) -> Result<Value, RpcError>
+    where
+        W: Write,
+    {
+        match method {
+            "initialize" => Ok(self.initialize()),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/hover" => self.hover(parse(params)?),
+            "textDocument/codeAction" => self.code_action(parse(params)?),
+            "workspace/executeCommand" => self.execute_command(parse(params)?, writer),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        }
+    }
+
+    fn handle_notification<W>(&self, method: &str, params: Value, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        match method {
+            "textDocument/didOpen" => {
+                let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(params) else {
+                    return Ok(());
+                };
+                let document = params.text_document;
+                self.buffers.borrow_mut().insert(document.uri.clone(), document.text);
+                self.publish_diagnostics(&document.uri, writer)
+            }
+            "textDocument/didChange" => {
+                let Ok(params) = serde_json::from_value::<DidChangeTextDocumentParams>(params) else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.buffers.borrow_mut().insert(uri.clone(), change.text);
+                }
+                self.publish_diagnostics(&uri, writer)
+            }
+            "textDocument/didSave" => {
+                let Ok(params) = serde_json::from_value::<TextDocumentParams>(params) else {
+                    return Ok(());
+                };
+                // the file on disk is up to date now.
+                self.buffers.borrow_mut().remove(&params.text_document.uri);
+                self.publish_diagnostics(&params.text_document.uri, writer)
+            }
+            "textDocument/didClose" => {
+                let Ok(params) = serde_json::from_value::<TextDocumentParams>(params) else {
+                    return Ok(());
+                };
+                self.buffers.borrow_mut().remove(&params.text_document.uri);
+                let diagnostics = json!({ "uri": params.text_document.uri, "diagnostics": [] });
+                write_framed(writer, &Notification::new("textDocument/publishDiagnostics", diagnostics))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn initialize(&self) -> Value {
+        json!({
+            "capabilities": {
+                "textDocumentSync": { "openClose": true, "change": 1, "save": { "includeText": false } },
+                "hoverProvider": true,
+                "codeActionProvider": true,
+                "executeCommandProvider": { "commands": [ADD_COMMAND, EDIT_COMMAND, DELETE_COMMAND] },
+            },
+            "serverInfo": { "name": "git-note", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn hover(&self, params: TextDocumentPositionParams) -> Result<Value, RpcError> {
+        let uri = params.text_document.uri;
+        let Ok(paths) = paths_of(&uri) else {
+            return Ok(Value::Null); // not a file in git repository
+        };
+        if paths.canonical().is_dir() || is_binary(&paths).map_err(internal)? {
+            return Ok(Value::Null); // no line to hover
+        }
+        let messages = self.messages(&uri, &paths).map_err(internal)?;
+        let contents = messages.iter()
+            .filter(|m| m.scope == Scope::Line && m.line == params.position.line)
+            .map(|m| m.message.clone())
+            .collect::<Vec<_>>();
+        if contents.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(json!({ "contents": { "kind": "markdown", "value": contents.join("\n\n---\n\n") } }))
+    }
+
+    fn code_action(&self, params: CodeActionParams) -> Result<Value, RpcError> {
+        let uri = params.text_document.uri;
+        let line = params.range.start.line;
+        let Ok(paths) = paths_of(&uri) else {
+            return Ok(json!([])); // not a file in git repository
+        };
+        let exists = self.messages(&uri, &paths).map_err(internal)?
+            .iter()
+            .any(|m| m.scope == Scope::Line && m.line == line);
+
+        let titles = if exists {
+            vec![("Edit note", EDIT_COMMAND), ("Delete note", DELETE_COMMAND)]
+        } else {
+            vec![("Add note", ADD_COMMAND)]
+        };
+        let actions = titles.into_iter()
+            .map(|(title, command)| CodeAction {
+                title: title.to_string(),
+                kind: "quickfix",
+                command: Command {
+                    title: title.to_string(),
+                    command: command.to_string(),
+                    arguments: vec![NoteCommandArgs { uri: uri.clone(), line: Some(line), message: None }],
+                },
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_value(actions).map_err(internal)
+    }
+
+    fn execute_command<W>(&self, params: ExecuteCommandParams, writer: &mut W) -> Result<Value, RpcError>
+    where
+        W: Write,
+    {
+        let argument = params.arguments.into_iter()
+            .next()
+            .ok_or(RpcError::new(INVALID_PARAMS, "an argument is required"))?;
+        let command_args: NoteCommandArgs = parse(argument)?;
+        let requires_message = params.command != DELETE_COMMAND;
+        if requires_message && command_args.message.is_none() {
+            return Err(RpcError::new(INVALID_PARAMS, format!("`message` argument is required for `{}`", params.command)));
+        }
+        let args = LspNoteArgs {
+            paths: paths_of(&command_args.uri)?,
+            line: command_args.line,
+            message: command_args.message,
+            content: self.buffer(&command_args.uri),
+        };
+
+        match params.command.as_str() {
+            ADD_COMMAND => self.note_handler.add_note(&args),
+            EDIT_COMMAND => self.note_handler.edit_note(&args),
+            DELETE_COMMAND => self.note_handler.delete_note(&args),
+            command => return Err(RpcError::new(INVALID_PARAMS, format!("unknown command `{}`", command))),
+        }.map_err(internal)?;
+
+        self.publish_diagnostics(&command_args.uri, writer).map_err(internal)?;
+        Ok(Value::Null)
+    }
+
+    fn publish_diagnostics<W>(&self, uri: &str, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: Write,
+    {
+        let Ok(paths) = paths_of(uri) else {
+            return Ok(()); // not a file in git repository
+        };
+        let args = LspNoteArgs { paths, line: None, message: None, content: self.buffer(uri) };
+        let ledger = self.note_handler.read_note(&args)?;
+        let note = ledger.opaque_note()?;
+        let content = ledger.content().unwrap_or_default();
+        let rows = content.lines().collect::<Vec<_>>();
+
+        let diagnostics = note.messages()
+            .into_iter()
+            .map(|m| {
+                let (line, message) = match m.scope {
+                    Scope::Line => (m.line, m.message.clone()),
+                    _ => (0, format!("(file) {}", m.message)),
+                };
+                let width = rows.get(line).map_or(0, |row| row.encode_utf16().count());
+                Diagnostic {
+                    range: Range {
+                        start: Position { line, character: 0 },
+                        end: Position { line, character: width },
+                    },
+                    severity: INFORMATION,
+                    source: "git-note",
+                    message,
+                }
+            })
+            .collect::<Vec<_>>();
+        let params = json!({ "uri": uri, "diagnostics": diagnostics });
+        write_framed(writer, &Notification::new("textDocument/publishDiagnostics", params))
+    }
+
+    fn messages(&self, uri: &str, paths: &Paths) -> anyhow::Result<Vec<Message>> {
+        let args = LspNoteArgs { paths: paths.clone(), line: None, message: None, content: self.buffer(uri) };
+        let note = self.note_handler.read_note(&args)?.opaque_note()?;
+        Ok(note.messages)
+    }
+
+    fn buffer(&self, uri: &str) -> Option<String> {
+        self.buffers.borrow().get(uri).cloned()
+    }
+}
+
+struct LspNoteArgs {
+    paths: Paths,
+    /// line number of LSP, which starts from 0
+    line: Option<usize>,
+    message: Option<String>,
+    content: Option<String>,
+}
+
+impl NoteArgs for LspNoteArgs {
+    fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    fn user_line(&self) -> usize {
+        self.sys_line() + 1
+    }
+
+    fn sys_line(&self) -> usize {
+        self.line.expect("line is not given for a file note")
+    }
+
+    fn message(&self) -> String {
+        self.message.clone().unwrap_or_default()
+    }
+
+    fn scope(&self) -> Scope {
+        match self.line {
+            Some(_) => Scope::Line,
+            None => Scope::File,
+        }
+    }
+
+    fn content(&self) -> Option<String> {
+        self.content.clone()
+    }
+}
+
+fn paths_of(uri: &str) -> Result<Paths, RpcError> {
+    let path = uri_to_path(uri).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+    let parent = path.parent().unwrap_or(&path).to_path_buf();
+    PathResolver::resolve(&parent, &path.to_string_lossy()).map_err(internal)
+}
+
+fn parse<P>(params: Value) -> Result<P, RpcError>
+where
+    P: DeserializeOwned,
+{
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn internal<E>(error: E) -> RpcError
+where
+    E: ToString,
+{
+    RpcError::new(INTERNAL_ERROR, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::{json, Value};
+
+    use crate::diff::SimilarDiffer;
+    use crate::handlers::NoteHandler;
+    use crate::jsonrpc::{read_framed, write_framed};
+    use crate::libgit::ManualLibgit;
+    use crate::lsp::LspServer;
+    use crate::note::Note;
+    use crate::path::PathResolver;
+    use crate::repository::NoteRepository;
+    use crate::testlib::TestRepo;
+
+    fn run(messages: Vec<Value>) -> anyhow::Result<Vec<Value>> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_framed(&mut input, &message)?;
+        }
+        let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+        let mut output = Vec::new();
+        LspServer::new(handler).run(&mut Cursor::new(input), &mut output)?;
+
+        let mut reader = Cursor::new(output);
+        let mut outputs = Vec::new();
+        while let Some(body) = read_framed(&mut reader)? {
+            outputs.push(serde_json::from_str(&body)?);
+        }
+        Ok(outputs)
+    }
+
+    #[test]
+    fn lifecycle() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+        let uri = format!("file://{}", path.display());
+        let document = json!({ "textDocument": { "uri": uri, "languageId": "plaintext", "version": 1, "text": "foo\nbar\nbaz" } });
+
+        // when
+        let outputs = run(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/codeAction", "params": { "textDocument": { "uri": uri }, "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 0 } } } }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/executeCommand", "params": { "command": "gitnote.add", "arguments": [{ "uri": uri, "line": 1, "message": "hello" }] } }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": document }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/hover", "params": { "textDocument": { "uri": uri }, "position": { "line": 1, "character": 1 } } }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "workspace/executeCommand", "params": { "command": "gitnote.delete", "arguments": [{ "uri": uri, "line": 1 }] } }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ])?;
+
+        // then
+        assert_eq!(outputs[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(outputs[1]["result"][0]["command"]["command"], "gitnote.add");
+        // published after add
+        assert_eq!(outputs[2]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(outputs[2]["params"]["diagnostics"][0]["message"], "hello");
+        assert_eq!(outputs[2]["params"]["diagnostics"][0]["range"]["end"]["character"], 3);
+        assert_eq!(outputs[3]["id"], 3);
+        // published on open
+        assert_eq!(outputs[4]["params"]["diagnostics"][0]["range"]["start"]["line"], 1);
+        assert_eq!(outputs[5]["result"]["contents"]["value"], "hello");
+        assert_eq!(outputs[6]["params"]["diagnostics"], json!([]));
+        assert_eq!(outputs[7]["id"], 5);
+        assert_eq!(outputs[8]["id"], 6);
+        assert_eq!(outputs.len(), 9);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_write_nothing() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("test.txt", Some("foo\nbar"))?;
+        let uri = format!("file://{}", path.display());
+        let before = repo.git_files()?;
+
+        // when
+        let outputs = run(vec![
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": uri, "languageId": "plaintext", "version": 1, "text": "foo\nbar" } } }),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 0 } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/codeAction", "params": { "textDocument": { "uri": uri }, "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } } } }),
+        ])?;
+
+        // then
+        assert_eq!(outputs[0]["params"]["diagnostics"], json!([]));
+        assert_eq!(outputs[1]["result"], Value::Null);
+        assert_eq!(outputs[2]["result"][0]["command"]["command"], "gitnote.add");
+        assert_eq!(repo.git_files()?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn message_required() -> anyhow::Result<()> {
+        let outputs = run(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "workspace/executeCommand", "params": { "command": "gitnote.add", "arguments": [{ "uri": "file:///foo", "line": 1 }] } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "unknown" }),
+        ])?;
+        assert_eq!(outputs[0]["error"]["code"], crate::jsonrpc::INVALID_PARAMS);
+        assert_eq!(outputs[1]["error"]["code"], crate::jsonrpc::METHOD_NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn unsaved_changes() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+        let uri = format!("file://{}", path.display());
+
+        // when : a line is inserted above the note, but not saved
+        let outputs = run(vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "workspace/executeCommand", "params": { "command": "gitnote.add", "arguments": [{ "uri": uri, "line": 1, "message": "hello" }] } }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": uri, "languageId": "plaintext", "version": 1, "text": "foo\nbar\nbaz" } } }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": { "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": "new\nfoo\nbar\nbaz" }] } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": { "textDocument": { "uri": uri }, "position": { "line": 2, "character": 0 } } }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didSave", "params": { "textDocument": { "uri": uri } } }),
+        ])?;
+
+        // then
+        assert_eq!(outputs[2]["params"]["diagnostics"][0]["range"]["start"]["line"], 1);
+        assert_eq!(outputs[3]["params"]["diagnostics"][0]["range"]["start"]["line"], 2);
+        assert_eq!(outputs[4]["result"]["contents"]["value"], "hello");
+        // back to the file on disk, which is not changed actually
+        assert_eq!(outputs[5]["params"]["diagnostics"][0]["range"]["start"]["line"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn survives_failed_notification() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("test.txt", Some("foo"))?;
+        let binary = repo.create_file("test.bin", Some("foo\0bar"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let note_path = paths.note(&Note::get_id(&paths.relative())?)?;
+        paths.initialize(&note_path)?;
+        std::fs::write(&note_path, r#"{"version":1,"id":"#)?;
+        let uri = format!("file://{}", path.display());
+
+        // when
+        let outputs = run(vec![
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": uri, "languageId": "plaintext", "version": 1, "text": "foo" } } }),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": { "textDocument": { "uri": format!("file://{}", binary.display()) }, "position": { "line": 0, "character": 0 } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": { "textDocument": { "uri": "file:///not/in/repository.txt" }, "position": { "line": 0, "character": 0 } } }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+        ])?;
+
+        // then
+        assert_eq!(outputs[0]["method"], "window/logMessage");
+        assert_eq!(outputs[0]["params"]["type"], 1);
+        assert_eq!(outputs[1]["result"], Value::Null);
+        assert_eq!(outputs[2]["result"], Value::Null);
+        assert_eq!(outputs[3]["id"], 3);
+        Ok(())
+    }
+}
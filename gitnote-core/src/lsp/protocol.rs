@@ -0,0 +1,148 @@
+//! Subset of LSP types which git-note needs. Unknown fields are ignored while deserializing.
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentIdentifier {
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentItem {
+    pub uri: String,
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenTextDocumentParams {
+    pub text_document: TextDocumentItem,
+}
+
+/// Only full sync is supported, so that every change has the whole text without range.
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentContentChangeEvent {
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeTextDocumentParams {
+    pub text_document: TextDocumentIdentifier,
+    pub content_changes: Vec<TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentPositionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecuteCommandParams {
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Vec<serde_json::Value>,
+}
+
+/// Argument of `gitnote.*` commands.
+/// A note targets the whole file when `line` is absent, and `message` is required for add and edit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteCommandArgs {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: u8,
+    pub source: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    pub arguments: Vec<NoteCommandArgs>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CodeAction {
+    pub title: String,
+    pub kind: &'static str,
+    pub command: Command,
+}
+
+/// Converts `file://` uri into a local path, decoding percent-encoded bytes.
+pub fn uri_to_path(uri: &str) -> anyhow::Result<PathBuf> {
+    let encoded = uri.strip_prefix("file://")
+        .ok_or(anyhow!("unsupported uri `{}`, only `file://` is supported", uri))?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&encoded[i + 1..i + 3], 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    let path = String::from_utf8(decoded)?;
+    // `file:///C:/foo` on windows
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ => path,
+    };
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri() -> anyhow::Result<()> {
+        assert_eq!(uri_to_path("file:///foo/bar%20baz.rs")?, PathBuf::from("/foo/bar baz.rs"));
+        assert_eq!(uri_to_path("file:///C:/foo.rs")?, PathBuf::from("C:/foo.rs"));
+        assert!(uri_to_path("untitled:Untitled-1").is_err());
+        Ok(())
+    }
+}
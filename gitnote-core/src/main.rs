@@ -1,32 +1,51 @@
 use clap::Parser;
 
-use gitnote::cli::argument::{CliCommand, CliConfigSubcommand, CliSubcommand};
+use gitnote::cli::argument::{CliCommand, CliConfigSubcommand, CliGitNotesSubcommand, CliSubcommand};
 use gitnote::cli::CliCurator;
 use gitnote::cli::config::CliConfig;
-use gitnote::diff::SimilarDiffer;
+use gitnote::config::Config;
 use gitnote::handlers::NoteHandler;
-use gitnote::libgit::ManualLibgit;
+use gitnote::libgit::libgit_of;
 use gitnote::repository::NoteRepository;
 
-fn main() {
-    let libgit = ManualLibgit::new(SimilarDiffer);
-    let note_handler = NoteHandler::new(NoteRepository::new(libgit));
-    let cli_curator = CliCurator::new(note_handler);
+fn main() -> anyhow::Result<()> {
     let cli_command = CliCommand::parse();
+    let backend = match cli_command.libgit {
+        Some(backend) => backend,
+        None => Config::load(&cli_command.sub.paths()?)?.libgit(),
+    };
+    let note_handler = NoteHandler::new(NoteRepository::new(libgit_of(backend)));
+    let cli_curator = CliCurator::new(note_handler);
 
     match cli_command.sub {
         CliSubcommand::Add(args) => { cli_curator.add_note(args) }
         CliSubcommand::Read(args) => { cli_curator.read_note(args) }
+        CliSubcommand::Blame(args) => { cli_curator.blame_note(args) }
         CliSubcommand::Edit(args) => { cli_curator.edit_note(args) }
         CliSubcommand::Delete(args) => { cli_curator.delete_note(args) }
+        CliSubcommand::List(args) => { cli_curator.list_notes(args) }
+        CliSubcommand::Export(args) => { cli_curator.export_notes(args) }
+        CliSubcommand::Diff(args) => { cli_curator.diff_notes(args) }
+        CliSubcommand::ImportComments(args) => { cli_curator.import_comments(args) }
+        CliSubcommand::Inline(args) => { cli_curator.inline_notes(args) }
+        CliSubcommand::Lsp(args) => { cli_curator.lsp(args) }
+        CliSubcommand::Serve(args) => { cli_curator.serve(args) }
+        CliSubcommand::Migrate(args) => { cli_curator.migrate(args) }
+        CliSubcommand::Convert(args) => { cli_curator.convert(args) }
+        CliSubcommand::GitNotes(git_notes_command) => {
+            match git_notes_command {
+                CliGitNotesSubcommand::Export(args) => { cli_curator.export_git_notes(args) }
+                CliGitNotesSubcommand::Import(args) => { cli_curator.import_git_notes(args) }
+            }
+        }
         CliSubcommand::Config(config_command) => {
             let cli_config = CliConfig::new();
             let sub = match config_command {
                 CliConfigSubcommand::Set(args) => { cli_config.set(args) }
                 CliConfigSubcommand::Get(args) => { cli_config.get(args) }
                 CliConfigSubcommand::Show(_) => { cli_config.show() }
-            }.unwrap();
+            }?;
             Ok(sub)
         }
-    }.unwrap();
+    }
 }
\ No newline at end of file
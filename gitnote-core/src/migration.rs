@@ -0,0 +1,145 @@
+//! Upgrades note files written by older versions of git-note to [`NOTE_VERSION`].
+//!
+//! A note file is read as a plain json first, and each migration upgrades it by one version,
+//! so that a layout only has to be known until the next one. To change the layout,
+//! bump [`NOTE_VERSION`], append a migration, and add a golden file of the old layout under `testdata/notes`.
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::error::NoteError;
+use crate::note::{Note, NOTE_VERSION};
+
+type Migration = fn(Value) -> anyhow::Result<Value>;
+
+/// `MIGRATIONS[n]` upgrades a note of version `n` to `n + 1`.
+const MIGRATIONS: [Migration; NOTE_VERSION as usize] = [
+    v0_to_v1,
+    v1_to_v2,
+];
+
+pub fn version_of(value: &Value) -> anyhow::Result<u32> {
+    return match value.get("version") {
+        None => Ok(0),
+        Some(version) => version.as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or(anyhow!("invalid version of note : {}", version)),
+    };
+}
+
+/// Reads a note of any known version, upgrading it to the latest.
+pub fn migrate(mut value: Value) -> anyhow::Result<Note> {
+    let version = version_of(&value)?;
+    if version > NOTE_VERSION {
+        return Err(NoteError::UnsupportedVersion(version).into());
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        value = migration(value)?;
+    }
+    return Ok(serde_json::from_value(value)?);
+}
+
+/// Same as [`migrate`], telling which file is corrupt if the note can't be read.
+pub fn migrate_from(value: Value, source: &Path) -> anyhow::Result<Note> {
+    return migrate(value).map_err(|e| match e.downcast_ref::<NoteError>() {
+        Some(_) => e.context(format!("failed to read note {}", source.display())),
+        // a valid json, but not a note.
+        None => e.context(NoteError::Corrupt(source.to_path_buf())),
+    });
+}
+
+/// Files before versioning. Messages without `scope` are line notes, which the latest still reads as they are.
+fn v0_to_v1(mut value: Value) -> anyhow::Result<Value> {
+    let note = value.as_object_mut().ok_or(anyhow!("note is not an object"))?;
+    note.insert("version".to_string(), Value::from(1));
+    return Ok(value);
+}
+
+/// Messages gain `author` and `tags`, which are left empty as nobody knows them.
+fn v1_to_v2(mut value: Value) -> anyhow::Result<Value> {
+    let note = value.as_object_mut().ok_or(anyhow!("note is not an object"))?;
+    note.insert("version".to_string(), Value::from(2));
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::Value;
+
+    use crate::diff::SimilarDiffer;
+    use crate::error::NoteError;
+    use crate::libgit::ManualLibgit;
+    use crate::migration::{migrate, version_of};
+    use crate::note::{Note, NOTE_VERSION};
+    use crate::path::PathResolver;
+    use crate::repository::NoteRepository;
+    use crate::testlib::TestRepo;
+
+    fn golden(version: u32) -> anyhow::Result<String> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("testdata/notes/v{}.json", version));
+        return Ok(fs::read_to_string(path)?);
+    }
+
+    #[test]
+    fn every_layout() -> anyhow::Result<()> {
+        // given
+        let latest: Value = serde_json::from_str(&golden(NOTE_VERSION)?)?;
+
+        for version in 0..=NOTE_VERSION {
+            // when
+            let value: Value = serde_json::from_str(&golden(version)?)?;
+            let note = migrate(value)?;
+
+            // then
+            assert_eq!(version_of(&serde_json::from_str(&golden(version)?)?)?, version);
+            assert_eq!(note.version, NOTE_VERSION);
+            assert_eq!(serde_json::to_value(&note)?, latest, "v{} is not migrated to the latest", version);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn newer_version() -> anyhow::Result<()> {
+        // given
+        let mut value: Value = serde_json::from_str(&golden(NOTE_VERSION)?)?;
+        value["version"] = Value::from(NOTE_VERSION + 1);
+
+        // when
+        let result = migrate(value);
+
+        // then
+        let error = result.unwrap_err();
+        assert!(matches!(error.downcast_ref::<NoteError>(), Some(NoteError::UnsupportedVersion(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_all() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_dir("src")?;
+        repo.create_file("src/main.rs", Some("fn main() {}"))?;
+        let paths = PathResolver::resolve(repo.path(), "src/main.rs")?;
+        let note_path = paths.note(&Note::get_id(&paths.relative())?)?;
+        paths.initialize(&note_path)?;
+        fs::write(&note_path, golden(0)?)?;
+        let repository = NoteRepository::new(ManualLibgit::new(SimilarDiffer));
+
+        // when
+        let migrated = repository.migrate_all(&paths)?;
+        let again = repository.migrate_all(&paths)?;
+
+        // then
+        assert_eq!(migrated, 1);
+        assert_eq!(again, 0);
+        let value: Value = serde_json::from_str(&fs::read_to_string(&note_path)?)?;
+        assert_eq!(version_of(&value)?, NOTE_VERSION);
+        assert_eq!(repo.read_note(&note_path)?.messages.len(), 2);
+        Ok(())
+    }
+}
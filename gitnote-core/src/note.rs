@@ -1,16 +1,27 @@
+#[cfg(not(target_arch = "wasm32"))]
 use std::cell::{Ref, RefCell};
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+#[cfg(not(target_arch = "wasm32"))]
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::diff::DiffModel;
-use crate::libgit::{GitBlob, Libgit};
+use crate::diff::{Differ, DiffModel};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::config::Charset;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::libgit::{charset_of, GitBlob, Libgit};
+#[cfg(not(target_arch = "wasm32"))]
 use crate::path::Paths;
+#[cfg(not(target_arch = "wasm32"))]
 use crate::utils::PathBufExt;
 
+/// Reads and writes messages of a note, anchoring them to the current content of the file through [`Libgit`].
+#[cfg(not(target_arch = "wasm32"))]
 pub struct NoteLedger<'p, T>
 where
     T: Libgit,
@@ -18,8 +29,11 @@ where
     paths: Paths,
     libgit: &'p T,
     note: RefCell<Note>,
+    /// content to be taken as the current one instead of the file on disk, e.g. an unsaved buffer.
+    buffer: Option<String>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl<'p, T> NoteLedger<'p, T>
 where
     T: Libgit,
@@ -29,6 +43,19 @@ where
             paths: paths.clone(),
             libgit,
             note: RefCell::new(note),
+            buffer: None,
+        }
+    }
+
+    /// Anchors messages to given content instead of the file on disk, if any.
+    pub fn with_buffer(self, buffer: Option<String>) -> Self {
+        Self { buffer, ..self }
+    }
+
+    fn current_blob(&self, persist: bool) -> anyhow::Result<GitBlob> {
+        match &self.buffer {
+            Some(buffer) => self.libgit.make_git_blob_of(&self.paths, buffer, persist),
+            None => self.libgit.make_git_blob(&self.paths, persist),
         }
     }
 
@@ -36,10 +63,11 @@ where
         return self.note.borrow();
     }
 
-    pub fn opaque_note(&self) -> Note {
+    /// Fails if the charset of the file cannot be resolved, which every message on a line needs to be anchored.
+    pub fn opaque_note(&self) -> anyhow::Result<Note> {
         let note = self.note.borrow();
-        let messages = self.opaque_messages();
-        return Note::from(&note.id, &note.reference, messages);
+        let messages = self.opaque_messages()?;
+        return Ok(Note::from(&note.id, &note.reference, messages));
     }
 
     fn plain_messages(&self) -> Ref<Vec<Message>> {
@@ -48,44 +76,86 @@ where
     }
 
     /// Read note from file and filter out invalid messages
-    fn opaque_messages(&self) -> Vec<Message> {
+    fn opaque_messages(&self) -> anyhow::Result<Vec<Message>> {
+        if !self.plain_messages().iter().any(|m| m.scope == Scope::Line) {
+            return Ok(self.messages_on(None, Charset::default()));
+        }
+        // the current content is the same for every message, and so is the charset.
+        let charset = charset_of(&self.paths)?;
+        let new_blob = self.current_blob(false).ok();
+        return Ok(self.messages_on(new_blob.as_ref(), charset));
+    }
+
+    /// Same as [`NoteLedger::opaque_note`], but lines are anchored to given blob instead of the current content,
+    /// e.g. the file at `HEAD`. Messages on lines are all invalid without any blob.
+    pub fn opaque_note_at(&self, git_blob: &GitBlob) -> anyhow::Result<Note> {
+        let note = self.note.borrow();
+        return Ok(Note::from(&note.id, &note.reference, self.messages_on(Some(git_blob), charset_of(&self.paths)?)));
+    }
+
+    /// Every snapshot is decoded with `charset`, which is of the file.
+    fn messages_on(&self, new_blob: Option<&GitBlob>, charset: Charset) -> Vec<Message> {
         let plain = self.plain_messages();
+        // snapshots sharing an oid are read only once.
+        let mut old_blobs: HashMap<&String, Option<GitBlob>> = HashMap::new();
+
         return plain.iter()
             .filter_map(|m| {
-                let old_blob = self.libgit.read_git_blob(&self.paths, &m.oid).ok()?;
-                let new_blob = self.libgit.make_git_blob(&self.paths, false).ok()?;
-
-                let mut diff_model = DiffModel::of(m);
-                self.libgit.diff(&old_blob.content, &new_blob.content, &mut diff_model);
-
-                if diff_model.valid {
-                    Some(m.copied(diff_model.line, new_blob.id.clone()))
-                } else {
-                    None
+                if m.scope != Scope::Line {
+                    // not anchored to any line, so it is always valid.
+                    return Some(m.clone());
                 }
+                let new_blob = new_blob?;
+                let old_blob = old_blobs.entry(&m.oid)
+                    .or_insert_with(|| self.libgit.read_git_blob_as(&self.paths, &m.oid, charset).ok())
+                    .as_ref()?;
+
+                let line = m.reanchor(self.libgit.differ(), &old_blob.content, &new_blob.content)?;
+                Some(m.copied(line, new_blob.id.clone()))
             })
             .collect();
     }
 
     pub fn content(&self) -> anyhow::Result<String> {
-        Ok(self.libgit.make_git_blob(&self.paths, false)?.content)
+        Ok(self.current_blob(false)?.content)
     }
 
-    pub fn opaque_exists(&self, line: usize) -> bool {
-        return self.opaque_messages().iter().any(|m| m.line == line);
+    pub fn opaque_exists(&self, line: usize) -> anyhow::Result<bool> {
+        return Ok(self.opaque_messages()?.iter().any(|m| m.scope == Scope::Line && m.line == line));
     }
 
-    pub fn opaque_uuid(&self, line: usize) -> Option<String> {
-        let messages = self.opaque_messages();
-        return messages.iter()
+    pub fn opaque_uuid(&self, line: usize) -> anyhow::Result<Option<String>> {
+        let messages = self.opaque_messages()?;
+        let uuid = messages.iter()
             .rev()
-            .find(|m| m.line == line)
+            .find(|m| m.scope == Scope::Line && m.line == line)
             .map(|m| m.uuid.clone());
+        return Ok(uuid);
     }
 
-    pub fn append(&self, line: usize, message: String) -> anyhow::Result<()> {
-        let git_blob = self.libgit.make_git_blob(&self.paths, true)?;
-        let message = Message::new(&git_blob, line, message)?;
+    /// Finds the latest message which is not anchored to a line but to the whole of given scope.
+    pub fn whole_uuid(&self, scope: &Scope) -> Option<String> {
+        return self.plain_messages().iter()
+            .rev()
+            .find(|m| &m.scope == scope)
+            .map(|m| m.uuid.clone());
+    }
+
+    pub fn append_whole(&self, scope: Scope, message: String, author: Option<String>, tags: Vec<String>) -> anyhow::Result<()> {
+        let message = Message::whole(scope, message).signed(author, tags);
+        self.note.borrow_mut().append(message)?;
+        return Ok(());
+    }
+
+    pub fn append(&self, line: usize, message: String, author: Option<String>, tags: Vec<String>) -> anyhow::Result<()> {
+        let git_blob = self.current_blob(true)?;
+        return self.append_to(&git_blob, line, message, author, tags);
+    }
+
+    /// Same as [`NoteLedger::append`], but anchored to the line of given blob instead of the current content,
+    /// e.g. the file at a commit. The blob has to be stored in the repository.
+    pub fn append_to(&self, git_blob: &GitBlob, line: usize, message: String, author: Option<String>, tags: Vec<String>) -> anyhow::Result<()> {
+        let message = Message::new(git_blob, line, message)?.signed(author, tags);
         self.note.borrow_mut().append(message)?;
         return Ok(());
     }
@@ -104,8 +174,15 @@ where
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Version of the note file format, which is bumped whenever the layout changes.
+/// Files of older versions are upgraded on read by `migration` module.
+pub const NOTE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Note {
+    /// Files written before versioning have no version, which is regarded as 0.
+    #[serde(default)]
+    pub version: u32,
     pub id: String,
     pub reference: PathBuf,
     pub messages: Vec<Message>,
@@ -118,6 +195,7 @@ impl Note {
 
     pub fn from(id: &String, reference: &PathBuf, messages: Vec<Message>) -> Self {
         Note {
+            version: NOTE_VERSION,
             id: id.to_owned(),
             reference: reference.to_owned(),
             messages,
@@ -125,6 +203,7 @@ impl Note {
     }
 
     /// Given path should be relative from root of the repository
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn get_id(path: &PathBuf) -> anyhow::Result<String> {
         return Ok(sha256::digest(path.try_to_str()?));
     }
@@ -139,7 +218,31 @@ impl Note {
     }
 
     pub fn find(&self, line: usize) -> Option<&Message> {
-        return self.messages.iter().rev().find(|m| m.line == line);
+        return self.messages.iter().rev().find(|m| m.scope == Scope::Line && m.line == line);
+    }
+
+    /// Messages not anchored to any line, which describe the whole file or directory.
+    pub fn wholes(&self) -> Vec<&Message> {
+        return self.messages.iter().filter(|m| m.scope != Scope::Line).collect();
+    }
+}
+
+/// What a message describes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// A single line, which is anchored by [`DiffModel`] as the file changes.
+    #[default]
+    Line,
+    /// The whole file, e.g. a binary one which has no line to anchor.
+    File,
+    /// The whole directory, e.g. a module.
+    Directory,
+}
+
+impl Scope {
+    fn is_line(&self) -> bool {
+        *self == Scope::Line
     }
 }
 
@@ -154,9 +257,17 @@ pub struct Message {
     created_at: DateTime<Utc>,
     #[serde(with = "datetime")]
     pub updated_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Scope::is_line")]
+    pub scope: Scope,
+    /// Who wrote it, e.g. `Jane Doe <jane@example.com>`, which is unknown for messages before version 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
 impl Message {
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn new(git_blob: &GitBlob, line: usize, message: String) -> anyhow::Result<Self> {
         let snippet = git_blob.snippet(line)
             .ok_or(anyhow!("specified line `{}` extends limit for file {:?}",line,&git_blob.file_path))?;
@@ -169,9 +280,56 @@ impl Message {
             message,
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            scope: Scope::Line,
+            author: None,
+            tags: Vec::new(),
         })
     }
 
+    /// A message without any line, which has no snapshot to be anchored to.
+    pub fn whole(scope: Scope, message: String) -> Self {
+        Message {
+            uuid: Uuid::new_v4().to_string(),
+            oid: String::new(),
+            line: 0,
+            snippet: String::new(),
+            message,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            scope,
+            author: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// A message on the line of given snippet, without any blob to read it from.
+    #[cfg(test)]
+    pub fn on_line(line: usize, snippet: &str, message: &str) -> Self {
+        Message { line, snippet: snippet.to_string(), ..Self::whole(Scope::Line, message.to_string()) }
+    }
+
+    pub fn signed(mut self, author: Option<String>, tags: Vec<String>) -> Self {
+        self.author = author;
+        self.tags = tags;
+        self
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    /// Follows the line of this message from `old` content, which is the snapshot it was written against, to `new` content.
+    /// Returns `None` if the line has been changed or removed, which means the message is no longer valid.
+    /// It needs neither git nor file system, so that it runs on wasm as well.
+    pub fn reanchor<D>(&self, differ: &D, old: &String, new: &String) -> Option<usize>
+    where
+        D: Differ + ?Sized,
+    {
+        let mut diff_model = DiffModel::of(self);
+        differ.diff(old, new, &mut diff_model);
+        diff_model.valid.then_some(diff_model.line)
+    }
+
     pub fn copied(&self, line: usize, oid: String) -> Self {
         Message {
             uuid: self.uuid.clone(),
@@ -181,6 +339,9 @@ impl Message {
             message: self.message.clone(),
             created_at: self.created_at.clone(),
             updated_at: self.updated_at.clone(),
+            scope: self.scope,
+            author: self.author.clone(),
+            tags: self.tags.clone(),
         }
     }
 
@@ -0,0 +1,269 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use flate2::read::ZlibDecoder;
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const OID_LEN: usize = 20;
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// A read-only view of packed objects under `.git/objects/pack`.
+///
+/// Once `git gc` finds an object reachable, it moves it from a loose file into a pack,
+/// so reading loose objects alone is not enough for blobs kept alive by git-note.
+/// Only version 2 index files are supported, which is the only version git writes today.
+pub struct PackedObjects {
+    packs: Vec<Pack>,
+}
+
+impl PackedObjects {
+    pub fn open(objects: &Path) -> anyhow::Result<Self> {
+        let pack_dir = objects.join("pack");
+        if !pack_dir.exists() {
+            return Ok(Self { packs: Vec::new() });
+        }
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(&pack_dir)? {
+            let idx_path = entry?.path();
+            if idx_path.extension().is_some_and(|ext| ext == "idx") {
+                packs.push(Pack::open(idx_path)?);
+            }
+        }
+        Ok(Self { packs })
+    }
+
+    pub fn contains(&self, oid: &str) -> bool {
+        self.packs.iter().any(|pack| pack.offset(oid).is_some())
+    }
+
+    /// Returns the type name and the inflated content of the object, if any pack has it.
+    pub fn read(&self, oid: &str) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        for pack in &self.packs {
+            if let Some(offset) = pack.offset(oid) {
+                let (kind, content) = pack.read_at(offset)?;
+                return Ok(Some((kind_name(kind)?.to_string(), content)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+struct Pack {
+    index: Vec<u8>,
+    count: usize,
+    pack_path: PathBuf,
+}
+
+impl Pack {
+    fn open(idx_path: PathBuf) -> anyhow::Result<Self> {
+        let index = fs::read(&idx_path)
+            .context(format!("Failed to read pack index at path: {:?}", idx_path))?;
+        if index.len() < 8 + 256 * 4 || index[0..4] != IDX_MAGIC || be_u32(&index, 4) != 2 {
+            return Err(anyhow!("unsupported pack index format at {:?}", idx_path));
+        }
+        let count = be_u32(&index, 8 + 255 * 4) as usize;
+        Ok(Self {
+            index,
+            count,
+            pack_path: idx_path.with_extension("pack"),
+        })
+    }
+
+    fn oid_at(&self, position: usize) -> &[u8] {
+        let start = 8 + 256 * 4 + position * OID_LEN;
+        &self.index[start..start + OID_LEN]
+    }
+
+    fn offset(&self, oid: &str) -> Option<u64> {
+        let target = hex_to_bytes(oid).ok()?;
+        let first = target[0] as usize;
+        let mut low = if first == 0 { 0 } else { be_u32(&self.index, 8 + (first - 1) * 4) as usize };
+        let mut high = be_u32(&self.index, 8 + first * 4) as usize;
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.oid_at(mid).cmp(&target[..]) {
+                std::cmp::Ordering::Equal => return Some(self.offset_at(mid)),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    fn offset_at(&self, position: usize) -> u64 {
+        // layout : header, fanout, oids, crc32s, 4-byte offsets, 8-byte offsets
+        let offsets = 8 + 256 * 4 + self.count * (OID_LEN + 4);
+        let offset = be_u32(&self.index, offsets + position * 4);
+        if offset & 0x8000_0000 == 0 {
+            return offset as u64;
+        }
+        let large = offsets + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        ((be_u32(&self.index, large) as u64) << 32) | be_u32(&self.index, large + 4) as u64
+    }
+
+    fn read_at(&self, offset: u64) -> anyhow::Result<(u8, Vec<u8>)> {
+        let mut reader = BufReader::new(File::open(&self.pack_path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut byte = read_byte(&mut reader)?;
+        let kind = (byte >> 4) & 0x07;
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?; // the remaining bits of the size are not needed
+        }
+
+        match kind {
+            OBJ_OFS_DELTA => {
+                let mut byte = read_byte(&mut reader)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = read_byte(&mut reader)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                let delta = inflate(reader)?;
+                let (base_kind, base) = self.read_at(offset - distance)?;
+                Ok((base_kind, apply_delta(&base, &delta)?))
+            }
+            OBJ_REF_DELTA => {
+                let mut base_oid = [0u8; OID_LEN];
+                reader.read_exact(&mut base_oid)?;
+                let base_oid = bytes_to_hex(&base_oid);
+                let delta = inflate(reader)?;
+                let base_offset = self.offset(&base_oid)
+                    .ok_or(anyhow!("base object `{}` is missing in {:?}", base_oid, self.pack_path))?;
+                let (base_kind, base) = self.read_at(base_offset)?;
+                Ok((base_kind, apply_delta(&base, &delta)?))
+            }
+            _ => Ok((kind, inflate(reader)?)),
+        }
+    }
+}
+
+fn kind_name(kind: u8) -> anyhow::Result<&'static str> {
+    match kind {
+        OBJ_COMMIT => Ok("commit"),
+        OBJ_TREE => Ok("tree"),
+        OBJ_BLOB => Ok("blob"),
+        OBJ_TAG => Ok("tag"),
+        _ => Err(anyhow!("unknown packed object type `{}`", kind)),
+    }
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut cursor = 0;
+    let _base_size = read_varint(delta, &mut cursor)?;
+    let result_size = read_varint(delta, &mut cursor)?;
+    let mut result = Vec::with_capacity(result_size);
+
+    while cursor < delta.len() {
+        let op = delta[cursor];
+        cursor += 1;
+        if op & 0x80 != 0 {
+            // copy from base
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (*delta.get(cursor).ok_or(anyhow!("truncated delta"))? as usize) << (i * 8);
+                    cursor += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= (*delta.get(cursor).ok_or(anyhow!("truncated delta"))? as usize) << (i * 8);
+                    cursor += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = base.get(offset..offset + size).ok_or(anyhow!("delta copies out of base object"))?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            // insert literal bytes
+            let chunk = delta.get(cursor..cursor + op as usize).ok_or(anyhow!("truncated delta"))?;
+            result.extend_from_slice(chunk);
+            cursor += op as usize;
+        } else {
+            return Err(anyhow!("unexpected delta opcode 0"));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(anyhow!("delta result size mismatch : expected {}, actual {}", result_size, result.len()));
+    }
+    Ok(result)
+}
+
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> anyhow::Result<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*cursor).ok_or(anyhow!("truncated delta header"))?;
+        *cursor += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn inflate<R: Read>(reader: R) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(reader).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> anyhow::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+pub fn hex_to_bytes(hex: &str) -> anyhow::Result<Vec<u8>> {
+    if hex.len() != OID_LEN * 2 {
+        return Err(anyhow!("invalid object id `{}`", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("invalid object id `{}`", hex)))
+        .collect()
+}
+
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_copy_and_insert() -> anyhow::Result<()> {
+        let base = b"hello world";
+        // base size 11, result size 11, copy 6 bytes from 0, insert "there"
+        let delta = [11, 11, 0x90, 6, 5, b't', b'h', b'e', b'r', b'e'];
+        assert_eq!(apply_delta(base, &delta)?, b"hello there");
+        Ok(())
+    }
+
+    #[test]
+    fn hex_round_trip() -> anyhow::Result<()> {
+        let oid = "f06840e105b1dd0b30b36bac387239359cd78f99";
+        assert_eq!(bytes_to_hex(&hex_to_bytes(oid)?), oid);
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use crate::config::Config;
 use crate::utils::create_file_if_not_exists;
 
 const NOTE_PATH: &'static str = ".git/notes";
+pub const SNAPSHOTS_REF: &str = "refs/gitnote/snapshots";
 
 #[derive(Debug)]
 pub struct PathResolver;
@@ -23,7 +24,6 @@ impl PathResolver {
     {
         let current_path = current_path.as_ref();
         let root = Self::root_by_recursive(current_path)?;
-        Self::initialize(&root)?;
 
         let canonical = PathBuf::from(current_path.join(input))
             .canonicalize()
@@ -47,13 +47,6 @@ impl PathResolver {
         Ok(current.to_path_buf())
     }
 
-    fn initialize(root: &Path) -> anyhow::Result<()> {
-        let note_path = root.join(NOTE_PATH);
-        create_file_if_not_exists(&note_path, "config.yml", Some(Config::default()))?;
-        create_file_if_not_exists(&note_path, "description", Some("This directory contains notes by `git-note`"))?;
-        Ok(())
-    }
-
     fn validate_path(root: &PathBuf, canonical: &PathBuf) -> anyhow::Result<()> {
         if !canonical.exists() || !canonical.starts_with(root) {
             return Err(anyhow!(
@@ -76,6 +69,9 @@ impl PathResolver {
 /// - home : `/foo/.git/notes`
 /// - config: `/foo/.git/notes/config.yml`
 /// - note : `/foo/.git/notes/12/34567890`
+/// - log : `/foo/.git/notes/log`
+/// - tree : `/foo/.git/notes/tree`
+/// - snapshots : `/foo/.git/refs/gitnote/snapshots`
 #[derive(Debug, Clone)]
 pub struct Paths {
     root: PathBuf,
@@ -107,18 +103,49 @@ impl Paths {
         self.root.join(".git/notes")
     }
 
+    pub fn snapshots(&self) -> PathBuf {
+        self.root.join(".git").join(SNAPSHOTS_REF)
+    }
+
+    /// Locked while [`Paths::snapshots`] is read, merged and written, apart from the `.lock` of git itself.
+    pub fn snapshots_lock(&self) -> PathBuf {
+        self.root.join(".git/gitnote-snapshots")
+    }
+
     pub fn config(&self) -> PathBuf {
         self.home().join("config.yml")
     }
 
+    /// The single file of every note, when they are stored in [`crate::config::Storage::Log`].
+    pub fn log(&self) -> PathBuf {
+        self.home().join("log")
+    }
+
+    /// The root of notes mirroring the source tree, when they are stored in [`crate::config::Storage::Tree`].
+    pub fn tree(&self) -> PathBuf {
+        self.home().join("tree")
+    }
+
+    /// Where the note of given id is stored, which may not exist yet.
     pub fn note(&self, id: &String) -> anyhow::Result<PathBuf> {
         let home = self.home();
         let dir = &id[0..2];
         let file = &id[2..];
-        ensure_dir(&home.join(dir))?;
         let note_path = home.join(dir).join(file);
         Ok(note_path)
     }
+
+    /// Creates the home of notes, and the directory of given note path inside it.
+    /// Only writers call this, so that reading never touches `.git`, which may even be read-only.
+    pub fn initialize(&self, note_path: &Path) -> anyhow::Result<()> {
+        let home = self.root.join(NOTE_PATH);
+        create_file_if_not_exists(&home, "config.yml", Some(Config::default()))?;
+        create_file_if_not_exists(&home, "description", Some("This directory contains notes by `git-note`"))?;
+        if let Some(dir) = note_path.parent() {
+            ensure_dir(&dir.to_path_buf())?;
+        }
+        Ok(())
+    }
 }
 
 impl Display for Paths {
@@ -158,6 +185,7 @@ mod tests {
         assert_eq!(paths.canonical(), path);
         assert_eq!(paths.home(), repo.path().join(".git/notes"));
         assert_eq!(paths.relative(), PathBuf::from("foo.txt"));
+        assert!(!paths.home().exists()); // initialized by the first write only
         Ok(())
     }
 
@@ -0,0 +1,224 @@
+//! Python bindings, built with `python` feature.
+//!
+//! ```python
+//! import gitnote
+//!
+//! repo = gitnote.Repository("/path/to/repo")
+//! repo.add("src/main.rs", "entry point", line=3)
+//! for note in repo.notes("src"):
+//!     print(note["reference"], len(note["messages"]))
+//! ```
+//!
+//! Notes and messages are plain dicts with the same keys as `git note read --formatted` prints.
+//! Like CLI, `line` starts from 1, and a note without `line` targets the whole file or directory.
+
+// methods generated by `#[pymethods]` convert `PyResult` into itself.
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::binding::{note_handler, NoteLibArgs};
+use crate::note::Note;
+use crate::path::PathResolver;
+
+/// A git repository, or a directory inside of it, which paths are resolved from.
+#[pyclass(module = "gitnote")]
+pub struct Repository {
+    base: PathBuf,
+}
+
+#[pymethods]
+impl Repository {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        // resolving paths needs an absolute path to find the root of repository.
+        Ok(Repository { base: path.canonicalize()? })
+    }
+
+    /// Reads the note of the file, with messages which are still valid, anchored to the current content.
+    #[pyo3(signature = (file, content = None))]
+    fn read(&self, py: Python<'_>, file: &str, content: Option<String>) -> PyResult<PyObject> {
+        let args = self.args(file, None, None, content)?;
+        let note = note_handler(&args.paths).map_err(runtime)?.read_note(&args).map_err(runtime)?.opaque_note().map_err(runtime)?;
+        to_py(py, &note)
+    }
+
+    #[pyo3(signature = (file, message, line = None, content = None))]
+    fn add(&self, file: &str, message: String, line: Option<i32>, content: Option<String>) -> PyResult<()> {
+        let args = self.args(file, line, Some(message), content)?;
+        note_handler(&args.paths).map_err(runtime)?.add_note(&args).map_err(runtime)
+    }
+
+    #[pyo3(signature = (file, message, line = None, content = None))]
+    fn edit(&self, file: &str, message: String, line: Option<i32>, content: Option<String>) -> PyResult<()> {
+        let args = self.args(file, line, Some(message), content)?;
+        note_handler(&args.paths).map_err(runtime)?.edit_note(&args).map_err(runtime)
+    }
+
+    #[pyo3(signature = (file, line = None, content = None))]
+    fn delete(&self, file: &str, line: Option<i32>, content: Option<String>) -> PyResult<()> {
+        let args = self.args(file, line, None, content)?;
+        note_handler(&args.paths).map_err(runtime)?.delete_note(&args).map_err(runtime)
+    }
+
+    /// Iterates every note with any valid message under the path, which is the whole repository by default.
+    #[pyo3(signature = (path = "."))]
+    fn notes(&self, path: &str) -> PyResult<NoteIterator> {
+        let args = self.args(path, None, None, None)?;
+        let notes = note_handler(&args.paths).map_err(runtime)?.list_notes(&args).map_err(runtime)?.notes;
+        Ok(NoteIterator { notes: notes.into_iter() })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Repository({:?})", self.base)
+    }
+}
+
+impl Repository {
+    fn args(&self, file: &str, line: Option<i32>, message: Option<String>, content: Option<String>) -> PyResult<NoteLibArgs> {
+        // `line` starts from 1, so that 0 or a negative line is not taken as any line.
+        if let Some(line) = line.filter(|line| *line < 1) {
+            return Err(PyValueError::new_err(format!("line starts from 1, but {} is given", line)));
+        }
+        Ok(NoteLibArgs {
+            paths: PathResolver::resolve(&self.base, file).map_err(runtime)?,
+            line,
+            message,
+            content,
+        })
+    }
+}
+
+/// Converts a note into a dict lazily, as a repository may have a lot of notes.
+#[pyclass(module = "gitnote")]
+pub struct NoteIterator {
+    notes: std::vec::IntoIter<Note>,
+}
+
+#[pymethods]
+impl NoteIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        slf.notes.next()
+            .map(|note| to_py(py, &note))
+            .transpose()
+    }
+}
+
+#[pymodule]
+fn gitnote(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Repository>()?;
+    m.add_class::<NoteIterator>()?;
+    Ok(())
+}
+
+fn to_py<T>(py: Python<'_>, value: &T) -> PyResult<PyObject>
+where
+    T: Serialize,
+{
+    let value = serde_json::to_value(value).map_err(runtime)?;
+    json_to_py(py, &value)
+}
+
+fn json_to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    let object = match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into_py(py),
+            None => n.as_f64().unwrap_or_default().into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(values) => {
+            let list = PyList::empty_bound(py);
+            for value in values {
+                list.append(json_to_py(py, value)?)?;
+            }
+            list.into_py(py)
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in map {
+                dict.set_item(key, json_to_py(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+    };
+    Ok(object)
+}
+
+fn runtime<E>(error: E) -> PyErr
+where
+    E: Into<anyhow::Error>,
+{
+    PyRuntimeError::new_err(format!("{:#}", error.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    use crate::python::gitnote;
+    use crate::testlib::TestRepo;
+
+    #[test]
+    fn scripting() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("a.txt", Some("foo\nbar\nbaz"))?;
+        repo.create_file("b.txt", Some("foo"))?;
+        pyo3::append_to_inittab!(gitnote);
+        pyo3::prepare_freethreaded_python();
+
+        // when
+        let result: anyhow::Result<()> = Python::with_gil(|py| {
+            let locals = PyDict::new_bound(py);
+            locals.set_item("path", repo.path())?;
+            py.run_bound(r#"
+import gitnote
+
+repo = gitnote.Repository(path)
+repo.add("a.txt", "hello", line=2)
+repo.add("a.txt", "about a")
+repo.add("b.txt", "about b")
+repo.edit("b.txt", "more about b")
+try:
+    repo.add("a.txt", "again", line=2)
+    raise AssertionError("duplicated note is added")
+except RuntimeError as e:
+    assert "already exists" in str(e)
+
+note = repo.read("a.txt", content="new\nfoo\nbar\nbaz")
+lines = sorted(m["line"] for m in note["messages"] if "scope" not in m)
+assert lines == [2], lines
+
+counts = {n["reference"]: len(n["messages"]) for n in repo.notes()}
+assert counts == {"a.txt": 2, "b.txt": 1}, counts
+repo.delete("b.txt")
+assert [n["reference"] for n in repo.notes()] == ["a.txt"]
+
+for line in (0, -1):
+    try:
+        repo.add("a.txt", "nowhere", line=line)
+        raise AssertionError("a note is added to line {}".format(line))
+    except ValueError as e:
+        assert "starts from 1" in str(e)
+"#, None, Some(&locals))?;
+            Ok(())
+        });
+
+        // then
+        result
+    }
+}
@@ -1,31 +1,69 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
+use std::path::PathBuf;
 
-use crate::diff::Differ;
+use anyhow::Context;
+
+use crate::config::{Config, Storage};
 use crate::libgit::Libgit;
+use crate::lock::NoteLock;
 use crate::note::{Note, NoteLedger};
 use crate::path::Paths;
+use crate::store::{store_of, ConfiguredStore, Listing, NoteStore};
+
+/// Notes are written before their snapshots are retained, so that a failure of the latter loses nothing but the protection from `git gc`.
+const SNAPSHOTS_NOT_RETAINED: &str = "The note is written, but its snapshots are not kept from `git gc`, which the next write retries";
 
 pub struct NoteRepository<T>
 where
     T: Libgit,
 {
     libgit: T,
+    store: Box<dyn NoteStore>,
 }
 
 impl<T> NoteRepository<T>
 where
     T: Libgit,
 {
+    /// Stores notes as `storage` in config of each repository says.
     pub fn new(libgit: T) -> Self {
-        Self { libgit }
+        Self::with_store(libgit, Box::new(ConfiguredStore))
+    }
+
+    pub fn with_store(libgit: T, store: Box<dyn NoteStore>) -> Self {
+        Self { libgit, store }
     }
 
     pub fn write_note(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
-        // create a file if it does not exist, and will truncate it if it does.
-        let file = File::create(paths.note(&note.id)?)?;
-        serde_json::to_writer(&file, note)?;
-        return Ok(());
+        return self.store.save(paths, note);
+    }
+
+    /// Locks the note of given paths against other writers, until the returned lock is dropped.
+    /// Take it before reading a note to modify, so that the note doesn't change in between.
+    /// Nothing is locked if the store is not shared with other processes.
+    ///
+    /// Only the directory to hold the lock is created, as the home of notes is initialized by the first write.
+    pub fn lock_note(&self, paths: &Paths) -> anyhow::Result<Option<NoteLock>> {
+        return match self.note_source(paths)? {
+            Some(source) => {
+                if let Some(dir) = source.parent() {
+                    fs::create_dir_all(dir).context(format!("Failed to create directory at path: {:?}", dir))?;
+                }
+                Ok(Some(NoteLock::acquire(&source)?))
+            }
+            None => Ok(None),
+        };
+    }
+
+    /// Tells whether the note of given paths has never been written, i.e. its source doesn't exist.
+    /// A note which is not shared with other processes is never told so.
+    pub fn never_written(&self, paths: &Paths) -> anyhow::Result<bool> {
+        return Ok(self.note_source(paths)?.is_some_and(|source| !source.exists()));
+    }
+
+    /// The file which the note of given paths is stored in, which is shared by every note in a log.
+    pub fn note_source(&self, paths: &Paths) -> anyhow::Result<Option<PathBuf>> {
+        return self.store.source(paths, &Note::get_id(&paths.relative())?);
     }
 
     pub fn read_note(&self, paths: &Paths) -> anyhow::Result<NoteLedger<T>> {
@@ -33,19 +71,263 @@ where
         return Ok(ledger);
     }
 
+    /// Reads every note stored in the repository of given paths, skipping the ones which cannot be read.
+    pub fn read_all_notes(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        return self.store.list(paths);
+    }
+
+    /// Rewrites every note of older versions in the latest format, returning how many are rewritten.
+    pub fn migrate_all(&self, paths: &Paths) -> anyhow::Result<usize> {
+        return self.store.migrate(paths);
+    }
+
+    /// Locks the private ref of snapshots while it is read, merged and written, so that writers of different notes
+    /// don't drop the blobs of each other. Nothing is locked for notes which are not shared with other processes.
+    fn lock_snapshots(&self, paths: &Paths) -> anyhow::Result<Option<NoteLock>> {
+        return match self.note_source(paths)? {
+            Some(_) => Ok(Some(NoteLock::acquire(&paths.snapshots_lock())?)),
+            None => Ok(None),
+        };
+    }
+
+    /// Adds the snapshot blobs of given note to the private ref which keeps them reachable, leaving the others as they are.
+    /// Blobs no longer referenced are let go by the next [`NoteRepository::retain_snapshots`].
+    pub fn retain_note_snapshots(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        let _lock = self.lock_snapshots(paths)?;
+        let mut oids = self.libgit.retained_blobs(paths).context(SNAPSHOTS_NOT_RETAINED)?;
+        oids.extend(note.messages.iter().map(|m| m.oid.clone()));
+        return self.libgit.retain_blobs(paths, &oids).context(SNAPSHOTS_NOT_RETAINED);
+    }
+
+    /// Refreshes the private ref which keeps every snapshot blob referenced by notes reachable.
+    /// If any note cannot be read, nothing retained is let go, as some of them may be of the note.
+    pub fn retain_snapshots(&self, paths: &Paths) -> anyhow::Result<()> {
+        let _lock = self.lock_snapshots(paths)?;
+        let listing = self.read_all_notes(paths).context(SNAPSHOTS_NOT_RETAINED)?;
+        let mut oids: Vec<String> = listing.notes.iter()
+            .flat_map(|note| note.messages.iter().map(|m| m.oid.clone()))
+            .collect();
+        if !listing.skipped.is_empty() {
+            oids.extend(self.libgit.retained_blobs(paths).context(SNAPSHOTS_NOT_RETAINED)?);
+        }
+        return self.libgit.retain_blobs(paths, &oids).context(SNAPSHOTS_NOT_RETAINED);
+    }
+
+    /// Tells whether the file is not a text, which has no line to note.
+    pub fn is_binary(&self, paths: &Paths) -> anyhow::Result<bool> {
+        return self.libgit.is_binary(paths);
+    }
+
     fn do_read_note(&self, paths: &Paths) -> anyhow::Result<NoteLedger<T>> {
         let file_path = paths.relative();
         let id = Note::get_id(&file_path)?;
-        let note_path = paths.note(&id)?;
-
-        let note = if note_path.exists() {
-            let file = File::open(&note_path)?;
-            serde_json::from_reader(BufReader::new(file))?
-        } else {
-            let note = Note::new(&id, &file_path);
-            self.write_note(paths, &note)?;
-            note
+        // a note is not stored until any message is written to it.
+        let note = self.store.load(paths, &id)?.unwrap_or_else(|| Note::new(&id, &file_path));
+        return Ok(self.ledger(paths, note));
+    }
+
+    /// Gives access to the history of the repository, e.g. commits and notes of git itself.
+    pub fn libgit(&self) -> &T {
+        return &self.libgit;
+    }
+
+    pub fn ledger(&self, paths: &Paths, note: Note) -> NoteLedger<T> {
+        return NoteLedger::new(paths, &self.libgit, note);
+    }
+
+    /// Moves every note into given storage of `.git/notes`, returning how many notes are moved.
+    /// Converting a log into a log compacts it, dropping superseded lines.
+    /// Other writers must not run meanwhile, as they may still write to the previous storage.
+    pub fn convert(&self, paths: &Paths, to: Storage) -> anyhow::Result<usize> {
+        let from = Config::load(paths)?.storage();
+        let notes = store_of(from).list(paths)?.complete()?;
+        store_of(to).import(paths, &notes)?;
+        Config::load(paths)?.with_storage(to).save(paths)?;
+
+        // the previous storage is cleared only after the config points to the new one.
+        if from != to {
+            store_of(from).clear(paths)?;
+        }
+        return Ok(notes.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::{Config, Storage};
+    use crate::diff::SimilarDiffer;
+    use crate::error::NoteError;
+    use crate::handlers::{NoteHandler, NoteQuery};
+    use crate::libgit::ManualLibgit;
+    use crate::note::{Note, NOTE_VERSION};
+    use crate::path::{PathResolver, Paths};
+    use crate::repository::NoteRepository;
+    use crate::testlib::{TestArgs, TestRepo};
+
+    fn jane() -> Option<String> {
+        Some("Jane Doe <jane@example.com>".to_string())
+    }
+
+    fn handler() -> NoteHandler<ManualLibgit<SimilarDiffer>> {
+        NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)))
+    }
+
+    /// What `list` shows, without ids and times which differ on every run.
+    fn listed(handler: &NoteHandler<ManualLibgit<SimilarDiffer>>, root: &Paths, query: NoteQuery) -> anyhow::Result<Vec<String>> {
+        let args = TestArgs { query, ..TestArgs::file(root, "") };
+        let listed = handler.list_notes(&args)?.notes
+            .iter()
+            .flat_map(|note| note.messages.iter().map(move |m| {
+                format!("{} {} {:?} {} {:?} {:?}", note.reference.display(), m.line, m.scope, m.message, m.tags, m.author)
+            }))
+            .collect();
+        Ok(listed)
+    }
+
+    /// Runs the same operations, and returns what is listed with a few queries.
+    fn scenario(storage: Storage) -> anyhow::Result<Vec<Vec<String>>> {
+        let repo = TestRepo::new();
+        repo.create_file("a.txt", Some("foo\nbar\nbaz"))?;
+        repo.create_file("b.txt", Some("qux"))?;
+        let root = PathResolver::resolve(repo.path(), ".")?;
+        root.initialize(&root.config())?;
+        Config::default().with_storage(storage).save(&root)?;
+        let a = PathResolver::resolve(repo.path(), "a.txt")?;
+        let b = PathResolver::resolve(repo.path(), "b.txt")?;
+        let handler = handler();
+
+        let add = |paths: &Paths, line: Option<usize>, message: &str, tags: &[&str]| {
+            let tags = tags.iter().map(|t| t.to_string()).collect();
+            handler.add_note(&TestArgs { line, tags, author: jane(), ..TestArgs::file(paths, message) })
         };
-        return Ok(NoteLedger::new(paths, &self.libgit, note));
+        add(&a, Some(1), "on foo", &["todo"])?;
+        add(&a, Some(2), "on bar", &[])?;
+        add(&a, Some(3), "on baz", &["todo", "perf"])?;
+        add(&b, None, "about b", &["perf"])?;
+        handler.edit_note(&TestArgs::line(&a, 2, "bar again"))?;
+        handler.delete_note(&TestArgs::line(&a, 1, ""))?;
+        assert!(add(&a, Some(3), "duplicated", &[]).is_err());
+
+        let read = handler.read_note(&TestArgs::file(&a, ""))?.opaque_note()?;
+        assert_eq!(read.messages.len(), 2);
+        return Ok(vec![
+            listed(&handler, &root, NoteQuery::default())?,
+            listed(&handler, &root, NoteQuery { tag: Some("perf".to_string()), ..Default::default() })?,
+            listed(&handler, &root, NoteQuery { author: Some("JANE".to_string()), ..Default::default() })?,
+            listed(&handler, &root, NoteQuery { author: Some("john".to_string()), ..Default::default() })?,
+            listed(&handler, &root, NoteQuery { until: Some(chrono::DateTime::UNIX_EPOCH), ..Default::default() })?,
+        ]);
+    }
+
+    #[test]
+    fn storages_behave_identically() -> anyhow::Result<()> {
+        // given, when
+        let files = scenario(Storage::Files)?;
+        let log = scenario(Storage::Log)?;
+        let tree = scenario(Storage::Tree)?;
+
+        // then
+        assert_eq!(files, log);
+        assert_eq!(files, tree);
+        assert_eq!(files[0].len(), 3);
+        assert_eq!(files[1], vec![
+            "a.txt 2 Line on baz [\"todo\", \"perf\"] Some(\"Jane Doe <jane@example.com>\")",
+            "b.txt 0 File about b [\"perf\"] Some(\"Jane Doe <jane@example.com>\")",
+        ]);
+        assert_eq!(files[2].len(), 3);
+        assert!(files[3].is_empty());
+        assert!(files[4].is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn convert() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("a.txt", Some("foo\nbar"))?;
+        let root = PathResolver::resolve(repo.path(), ".")?;
+        let a = PathResolver::resolve(repo.path(), "a.txt")?;
+        let handler = handler();
+        for line in [1, 2] {
+            handler.add_note(&TestArgs { author: jane(), ..TestArgs::line(&a, line, &format!("on {}", line)) })?;
+        }
+        handler.edit_note(&TestArgs::line(&a, 1, "edited"))?;
+        let before = listed(&handler, &root, NoteQuery::default())?;
+
+        // when
+        let to_log = handler.convert_notes(&root, Storage::Log)?;
+        let in_log = listed(&handler, &root, NoteQuery::default())?;
+        let note_source = handler.note_source(&a)?;
+        let to_tree = handler.convert_notes(&root, Storage::Tree)?;
+        let in_tree = listed(&handler, &root, NoteQuery::default())?;
+        let to_files = handler.convert_notes(&root, Storage::Files)?;
+        let in_files = listed(&handler, &root, NoteQuery::default())?;
+
+        // then
+        assert_eq!((to_log, to_tree, to_files), (1, 1, 1));
+        assert_eq!(note_source, Some(root.log()));
+        assert_eq!(before, in_log);
+        assert_eq!(before, in_tree);
+        assert_eq!(before, in_files);
+        assert!(!root.log().exists());
+        assert!(!root.tree().exists());
+        assert_eq!(Config::load(&root)?.storage(), Storage::Files);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_note() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let note_path = paths.note(&Note::get_id(&paths.relative())?)?;
+        paths.initialize(&note_path)?;
+        fs::write(&note_path, r#"{"version":1,"id":"#)?;
+        repo.create_file("other.txt", Some("bar"))?;
+        let other = PathResolver::resolve(repo.path(), "other.txt")?;
+        let repository = NoteRepository::new(ManualLibgit::new(SimilarDiffer));
+        repository.write_note(&other, &Note::new(&Note::get_id(&other.relative())?, &other.relative()))?;
+
+        // when
+        let read = repository.read_note(&paths).err().unwrap();
+        let listed = repository.read_all_notes(&paths)?;
+        let converted = repository.convert(&paths, Storage::Log).err().unwrap();
+
+        // then : listed except the corrupt one, which is told
+        assert_eq!(listed.notes.len(), 1);
+        assert_eq!(listed.notes[0].reference, other.relative());
+        assert_eq!(listed.skipped.len(), 1);
+        assert_eq!(listed.skipped[0].0, note_path);
+        let (_, skipped) = listed.skipped.into_iter().next().unwrap();
+        for error in [read, skipped, converted] {
+            assert!(matches!(error.downcast_ref::<NoteError>(), Some(NoteError::Corrupt(path)) if path == &note_path));
+        }
+        assert_eq!(fs::read_to_string(&note_path)?, r#"{"version":1,"id":"#);
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_temporary_files() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo"))?;
+        let paths = PathResolver::resolve(repo.path(), "test.txt")?;
+        let repository = NoteRepository::new(ManualLibgit::new(SimilarDiffer));
+        let note = Note::new(&Note::get_id(&paths.relative())?, &paths.relative());
+        repository.write_note(&paths, &note)?;
+        let note_path = paths.note(&note.id)?;
+        // left by a write which is interrupted before rename
+        fs::write(note_path.with_extension("1234-0.tmp"), "")?;
+
+        // when
+        let notes = repository.read_all_notes(&paths)?.notes;
+
+        // then
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].version, NOTE_VERSION);
+        Ok(())
     }
 }
@@ -0,0 +1,356 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::handlers::{NoteArgs, NoteHandler};
+use crate::jsonrpc::{Incoming, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, Response, RpcError, write_delimited};
+use crate::libgit::Libgit;
+use crate::note::{Note, Scope};
+use crate::path::{PathResolver, Paths};
+
+/// A long-running server which handles newline-delimited JSON-RPC requests, usually over stdio.
+///
+/// Unlike spawning `git note` for every operation, resolved paths and notes read are kept between requests.
+/// A cached note is used as long as neither the note file nor the annotated file has changed since,
+/// so writes by other processes such as CLI are still seen.
+///
+/// Supported methods are `read`, `add`, `edit`, `delete`, `list` and `shutdown`.
+/// `file` is resolved from the directory the server started in, and `line` starts from 1 as CLI does.
+/// `content` can be given to anchor lines to an unsaved buffer, and such reads are never cached.
+pub struct NoteServer<T>
+where
+    T: Libgit,
+{
+    note_handler: NoteHandler<T>,
+    base: PathBuf,
+    paths: RefCell<HashMap<String, Paths>>,
+    notes: RefCell<HashMap<PathBuf, CachedNote>>,
+}
+
+struct CachedNote {
+    stamp: Stamp,
+    note: Note,
+}
+
+/// Modification time and length of the note file and the annotated file.
+#[derive(PartialEq)]
+struct Stamp {
+    note: Option<(SystemTime, u64)>,
+    file: Option<(SystemTime, u64)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileParams {
+    file: String,
+    /// starts from 1, so that 0 is rejected as invalid params.
+    #[serde(default)]
+    line: Option<NonZeroUsize>,
+    #[serde(default)]
+    message: Option<String>,
+    /// unsaved content of the file, which is taken instead of the file on disk.
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+impl<T> NoteServer<T>
+where
+    T: Libgit,
+{
+    pub fn new<P>(note_handler: NoteHandler<T>, base: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            note_handler,
+            base: base.as_ref().to_path_buf(),
+            paths: RefCell::new(HashMap::new()),
+            notes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn run<R, W>(&self, reader: &mut R, writer: &mut W) -> anyhow::Result<()>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let incoming: Incoming = match serde_json::from_str(&line) {
+                Ok(incoming) => incoming,
+                Err(e) => {
+                    write_delimited(writer, &Response::err(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())))?;
+                    continue;
+                }
+            };
+            let (Some(id), Some(method)) = (incoming.id, incoming.method) else {
+                write_delimited(writer, &Response::err(Value::Null, RpcError::new(INVALID_REQUEST, "`id` and `method` are required")))?;
+                continue;
+            };
+            let response = match self.handle(&method, incoming.params) {
+                Ok(result) => Response::ok(id, result),
+                Err(error) => Response::err(id, error),
+            };
+            write_delimited(writer, &response)?;
+            if method == "shutdown" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "read" => self.read(parse(params)?),
+            "add" | "edit" | "delete" => self.write(method, parse(params)?),
+            "list" => self.list(parse(params)?),
+            "shutdown" => Ok(Value::Null),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        }
+    }
+
+    fn read(&self, params: FileParams) -> Result<Value, RpcError> {
+        let paths = self.resolve(&params.file)?;
+        if params.content.is_some() {
+            let args = ServeNoteArgs { paths, line: None, message: None, content: params.content };
+            let note = self.note_handler.read_note(&args).map_err(internal)?.opaque_note().map_err(internal)?;
+            return serde_json::to_value(&note).map_err(internal);
+        }
+        let stamp = self.stamp(&paths);
+        if let Some(cached) = self.notes.borrow().get(&paths.relative()) {
+            if cached.stamp == stamp {
+                return serde_json::to_value(&cached.note).map_err(internal);
+            }
+        }
+
+        let args = ServeNoteArgs { paths: paths.clone(), line: None, message: None, content: None };
+        let note = self.note_handler.read_note(&args).map_err(internal)?.opaque_note().map_err(internal)?;
+        let value = serde_json::to_value(&note).map_err(internal)?;
+        self.notes.borrow_mut().insert(paths.relative(), CachedNote { stamp, note });
+        Ok(value)
+    }
+
+    fn write(&self, method: &str, params: FileParams) -> Result<Value, RpcError> {
+        if method != "delete" && params.message.is_none() {
+            return Err(RpcError::new(INVALID_PARAMS, format!("`message` is required for `{}`", method)));
+        }
+        let args = ServeNoteArgs {
+            paths: self.resolve(&params.file)?,
+            line: params.line.map(NonZeroUsize::get),
+            message: params.message,
+            content: params.content,
+        };
+        self.notes.borrow_mut().remove(&args.paths.relative());
+        match method {
+            "add" => self.note_handler.add_note(&args),
+            "edit" => self.note_handler.edit_note(&args),
+            _ => self.note_handler.delete_note(&args),
+        }.map_err(internal)?;
+        Ok(Value::Null)
+    }
+
+    fn list(&self, params: ListParams) -> Result<Value, RpcError> {
+        let paths = self.resolve(params.path.as_deref().unwrap_or("."))?;
+        let args = ServeNoteArgs { paths, line: None, message: None, content: None };
+        let notes = self.note_handler.list_notes(&args).map_err(internal)?.notes;
+        serde_json::to_value(notes).map_err(internal)
+    }
+
+    fn resolve(&self, file: &str) -> Result<Paths, RpcError> {
+        if let Some(paths) = self.paths.borrow().get(file) {
+            return Ok(paths.clone());
+        }
+        let paths = PathResolver::resolve(&self.base, file).map_err(internal)?;
+        self.paths.borrow_mut().insert(file.to_string(), paths.clone());
+        Ok(paths)
+    }
+
+    fn stamp(&self, paths: &Paths) -> Stamp {
+        let metadata = |path: &Path| {
+            fs::metadata(path).ok().and_then(|m| Some((m.modified().ok()?, m.len())))
+        };
+        let note = self.note_handler.note_source(paths)
+            .ok()
+            .flatten()
+            .and_then(|note_path| metadata(&note_path));
+        Stamp {
+            note,
+            file: metadata(&paths.canonical()),
+        }
+    }
+}
+
+struct ServeNoteArgs {
+    paths: Paths,
+    line: Option<usize>,
+    message: Option<String>,
+    content: Option<String>,
+}
+
+impl NoteArgs for ServeNoteArgs {
+    fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    fn user_line(&self) -> usize {
+        self.line.expect("line is not given for a file note")
+    }
+
+    fn sys_line(&self) -> usize {
+        self.user_line() - 1
+    }
+
+    fn message(&self) -> String {
+        self.message.clone().unwrap_or_default()
+    }
+
+    fn scope(&self) -> Scope {
+        match self.line {
+            Some(_) => Scope::Line,
+            None => Scope::File,
+        }
+    }
+
+    fn content(&self) -> Option<String> {
+        self.content.clone()
+    }
+}
+
+fn parse<P>(params: Value) -> Result<P, RpcError>
+where
+    P: DeserializeOwned,
+{
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn internal<E>(error: E) -> RpcError
+where
+    E: ToString,
+{
+    RpcError::new(INTERNAL_ERROR, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::{json, Value};
+
+    use crate::diff::SimilarDiffer;
+    use crate::handlers::NoteHandler;
+    use crate::libgit::ManualLibgit;
+    use crate::repository::NoteRepository;
+    use crate::serve::NoteServer;
+    use crate::testlib::TestRepo;
+
+    fn run(repo: &TestRepo, requests: Vec<Value>) -> anyhow::Result<Vec<Value>> {
+        let input = requests.iter()
+            .map(|request| request.to_string() + "\n")
+            .collect::<String>();
+        let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+        let mut output = Vec::new();
+        NoteServer::new(handler, repo.path()).run(&mut Cursor::new(input), &mut output)?;
+        String::from_utf8(output)?
+            .lines()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    #[test]
+    fn requests() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+
+        // when
+        let responses = run(&repo, vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "read", "params": { "file": "test.txt" } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "add", "params": { "file": "test.txt", "line": 2, "message": "hello" } }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "read", "params": { "file": "test.txt" } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "read", "params": { "file": "test.txt", "content": "new\nfoo\nbar\nbaz" } }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "edit", "params": { "file": "test.txt", "line": 2, "message": "world" } }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "list", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "delete", "params": { "file": "test.txt", "line": 2 } }),
+            json!({ "jsonrpc": "2.0", "id": 8, "method": "read", "params": { "file": "test.txt" } }),
+            json!({ "jsonrpc": "2.0", "id": 9, "method": "edit", "params": { "file": "test.txt", "line": 2 } }),
+            json!({ "jsonrpc": "2.0", "id": 10, "method": "add", "params": { "file": "test.txt", "line": 0, "message": "zero" } }),
+            json!({ "jsonrpc": "2.0", "id": 11, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "id": 12, "method": "read", "params": { "file": "test.txt" } }),
+        ])?;
+
+        // then
+        assert_eq!(responses[0]["result"]["messages"], json!([]));
+        assert_eq!(responses[1]["result"], Value::Null);
+        assert_eq!(responses[2]["result"]["messages"][0]["line"], 1);
+        assert_eq!(responses[3]["result"]["messages"][0]["line"], 2); // follows unsaved content
+        assert_eq!(responses[5]["result"][0]["messages"][0]["message"], "world");
+        assert_eq!(responses[7]["result"]["messages"], json!([]));
+        assert_eq!(responses[8]["error"]["code"], crate::jsonrpc::INVALID_PARAMS);
+        assert_eq!(responses[9]["error"]["code"], crate::jsonrpc::INVALID_PARAMS); // lines start from 1
+        assert_eq!(responses.len(), 11); // stops after shutdown
+        Ok(())
+    }
+
+    #[test]
+    fn reads_write_nothing() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+        let before = repo.git_files()?;
+
+        // when
+        let responses = run(&repo, vec![
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "read", "params": { "file": "test.txt" } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "list", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "delete", "params": { "file": "test.txt", "line": 1 } }),
+        ])?;
+
+        // then
+        assert_eq!(responses[1]["result"], json!([]));
+        assert!(responses[2]["error"].is_object());
+        assert_eq!(repo.git_files()?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn sees_external_changes() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("test.txt", Some("foo\nbar\nbaz"))?;
+
+        // when : the file changes between reads, as IDE saves it
+        let handler = NoteHandler::new(NoteRepository::new(ManualLibgit::new(SimilarDiffer)));
+        let server = NoteServer::new(handler, repo.path());
+        let mut output = Vec::new();
+        let add = json!({ "jsonrpc": "2.0", "id": 1, "method": "add", "params": { "file": "test.txt", "line": 2, "message": "hello" } });
+        let read = json!({ "jsonrpc": "2.0", "id": 2, "method": "read", "params": { "file": "test.txt" } });
+        server.run(&mut Cursor::new(format!("{add}\n{read}\n")), &mut output)?;
+        repo.create_file("test.txt", Some("new\nfoo\nbar\nbaz"))?;
+        server.run(&mut Cursor::new(format!("{read}\n")), &mut output)?;
+
+        // then
+        let responses = String::from_utf8(output)?
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(responses[1]["result"]["messages"][0]["line"], 1);
+        assert_eq!(responses[2]["result"]["messages"][0]["line"], 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::error::NoteError;
+use crate::lock::NoteLock;
+use crate::migration::{migrate_from, version_of};
+use crate::note::{Note, NOTE_VERSION};
+use crate::path::Paths;
+use crate::store::{Listing, NoteStore};
+use crate::utils::write_atomically;
+
+/// A json file per annotated path, which is [`crate::config::Storage::Files`].
+/// Files are fanned out into directories named by the first two hex digits of their id, e.g. `.git/notes/12/34567890`.
+pub struct FileStore;
+
+impl NoteStore for FileStore {
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        let note_path = paths.note(id)?;
+        if !note_path.exists() {
+            return Ok(None);
+        }
+        return Ok(Some(read_note_file(&note_path)?));
+    }
+
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        // never truncates the note in place, which loses every message of the file if it is interrupted.
+        let content = serde_json::to_vec(note)?;
+        let note_path = paths.note(&note.id)?;
+        paths.initialize(&note_path)?;
+        return write_atomically(&note_path, &content);
+    }
+
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        let mut listing = Listing::default();
+        for note_path in note_files(paths)? {
+            listing.push(&note_path, read_note_file(&note_path))?;
+        }
+        return Ok(listing);
+    }
+
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()> {
+        let note_path = paths.note(id)?;
+        if note_path.exists() {
+            fs::remove_file(note_path)?;
+        }
+        return Ok(());
+    }
+
+    fn source(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<PathBuf>> {
+        return Ok(Some(paths.note(id)?));
+    }
+
+    fn migrate(&self, paths: &Paths) -> anyhow::Result<usize> {
+        let mut migrated = 0;
+        for note_path in note_files(paths)? {
+            let _lock = NoteLock::acquire(&note_path)?;
+            let value = read_value(&note_path)?;
+            if version_of(&value)? >= NOTE_VERSION {
+                continue;
+            }
+            let note = migrate_from(value, &note_path)?;
+            self.save(paths, &note)?;
+            migrated += 1;
+        }
+        return Ok(migrated);
+    }
+
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        for note_path in note_files(paths)? {
+            fs::remove_file(note_path)?;
+        }
+        return Ok(());
+    }
+}
+
+fn note_files(paths: &Paths) -> anyhow::Result<Vec<PathBuf>> {
+    let home = paths.home();
+    let mut files = Vec::new();
+    if !home.exists() {
+        return Ok(files);
+    }
+    for dir in fs::read_dir(&home)? {
+        let dir = dir?.path();
+        if !dir.is_dir() || !is_fanout(&dir) {
+            continue;
+        }
+        for file in fs::read_dir(&dir)? {
+            let file = file?.path();
+            // skips temporary files left by an interrupted write.
+            if is_hex(&file) {
+                files.push(file);
+            }
+        }
+    }
+    return Ok(files);
+}
+
+/// Reads a note file of any known version, upgrading it in memory. It is written in the latest format on the next write.
+fn read_note_file(note_path: &Path) -> anyhow::Result<Note> {
+    let value = read_value(note_path)?;
+    return migrate_from(value, note_path);
+}
+
+fn read_value(note_path: &Path) -> anyhow::Result<Value> {
+    let file = File::open(note_path)?;
+    return serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| anyhow::Error::new(e).context(NoteError::Corrupt(note_path.to_path_buf())));
+}
+
+/// note files are fanned out into directories named by the first two hex digits of their id.
+fn is_fanout(dir: &Path) -> bool {
+    is_hex(dir) && dir.file_name().is_some_and(|name| name.len() == 2)
+}
+
+fn is_hex(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -0,0 +1,500 @@
+//! Append-only log of notes, which is [`crate::config::Storage::Log`].
+//!
+//! Every write appends the whole note as a line of json to `.git/notes/log`, and the last line of a note wins.
+//! Deleting a note appends a line of `{"id": .., "deleted": true}` instead.
+//! Listing or searching reads a single file instead of opening a file per annotated path.
+//! A line cut off by a crash has no trailing newline, so it is ignored on read and dropped by the next append.
+//! A line which is not a note is skipped, and told by listing.
+//!
+//! `.git/notes/log.index` tells where the last line of every note is, so that loading or appending a note
+//! reads only the lines after those indexed. It is only a cache, which is rebuilt from the log if it doesn't match.
+//! Superseded lines are dropped by an append once they outnumber the notes, as well as by rewriting the log,
+//! e.g. `git note convert --to log`.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::NoteError;
+use crate::lock::NoteLock;
+use crate::migration::{migrate_from, version_of};
+use crate::note::{Note, NOTE_VERSION};
+use crate::path::Paths;
+use crate::store::{Listing, NoteStore};
+use crate::utils::write_atomically;
+
+/// The log is not compacted below this many lines, as it is cheap to read anyway.
+const COMPACT_LINES: usize = 256;
+
+/// A single append-only log of notes, which is [`crate::config::Storage::Log`].
+pub struct LogStore;
+
+impl NoteStore for LogStore {
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        return read(&paths.log(), id);
+    }
+
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        paths.initialize(&paths.log())?;
+        return append(&paths.log(), note);
+    }
+
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        return read_all(&paths.log());
+    }
+
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()> {
+        if read(&paths.log(), id)?.is_none() {
+            return Ok(());
+        }
+        return remove(&paths.log(), id);
+    }
+
+    fn source(&self, paths: &Paths, _id: &String) -> anyhow::Result<Option<PathBuf>> {
+        return Ok(Some(paths.log()));
+    }
+
+    fn migrate(&self, paths: &Paths) -> anyhow::Result<usize> {
+        if !paths.log().exists() {
+            return Ok(0);
+        }
+        let _lock = NoteLock::acquire(&paths.log())?;
+        if !outdated(&paths.log())? {
+            return Ok(0);
+        }
+        let notes = read_all(&paths.log())?.complete()?;
+        rewrite(&paths.log(), &notes)?;
+        return Ok(notes.len());
+    }
+
+    /// Rewrites the log with given notes only, which compacts it.
+    fn import(&self, paths: &Paths, notes: &[Note]) -> anyhow::Result<()> {
+        paths.initialize(&paths.log())?;
+        let _lock = NoteLock::acquire(&paths.log())?;
+        return rewrite(&paths.log(), notes);
+    }
+
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        if paths.log().exists() {
+            fs::remove_file(paths.log())?;
+        }
+        return remove_index(&paths.log());
+    }
+}
+
+/// Reads the latest of every note, in the order they are first written.
+/// Every note in the log, where lines which cannot be read are skipped.
+pub fn read_all(log_path: &Path) -> anyhow::Result<Listing> {
+    let mut listing = Listing::default();
+    let mut order = Vec::new();
+    let mut latest: HashMap<String, Value> = HashMap::new();
+    for record in parse_records(log_path)? {
+        let record = record.and_then(|record| Ok((id_of(&record, log_path)?, record)));
+        match record {
+            Ok((id, record)) => {
+                if latest.insert(id.clone(), record).is_none() {
+                    order.push(id);
+                }
+            }
+            Err(e) => listing.push(log_path, Err(e))?,
+        }
+    }
+    let records = order.into_iter()
+        .filter_map(|id| latest.remove(&id))
+        .filter(|record| !is_deleted(record));
+    for record in records {
+        listing.push(log_path, migrate_from(record, log_path))?;
+    }
+    return Ok(listing);
+}
+
+/// Reads the latest of the note, from the line which the index points to.
+pub fn read(log_path: &Path, id: &String) -> anyhow::Result<Option<Note>> {
+    let index = indexed(log_path)?;
+    let Some(&(offset, length)) = index.notes.get(id) else {
+        return Ok(None);
+    };
+    return match read_line_at(log_path, offset, length) {
+        Some(record) if id_of(&record, log_path).ok().as_ref() == Some(id) => migrate_from(record, log_path).map(Some),
+        _ => scan(log_path, id),
+    };
+}
+
+/// Reads the latest of the note by every line of the log, in case the index is wrong.
+fn scan(log_path: &Path, id: &String) -> anyhow::Result<Option<Note>> {
+    let mut found = None;
+    for record in parse_records(log_path)?.into_iter().flatten() {
+        if id_of(&record, log_path).ok().as_ref() == Some(id) {
+            found = Some(record);
+        }
+    }
+    return found.filter(|record| !is_deleted(record))
+        .map(|record| migrate_from(record, log_path))
+        .transpose();
+}
+
+/// Appends the note, which supersedes every line of the same note before.
+pub fn append(log_path: &Path, note: &Note) -> anyhow::Result<()> {
+    return append_line(log_path, serde_json::to_vec(note)?);
+}
+
+/// Appends a line which deletes the note.
+pub fn remove(log_path: &Path, id: &String) -> anyhow::Result<()> {
+    let tombstone = json!({ "version": NOTE_VERSION, "id": id, "deleted": true });
+    return append_line(log_path, serde_json::to_vec(&tombstone)?);
+}
+
+fn append_line(log_path: &Path, mut line: Vec<u8>) -> anyhow::Result<()> {
+    line.push(b'\n');
+    let mut index = indexed(log_path)?;
+    drop_torn_tail(log_path, index.length)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(log_path)?;
+    file.write_all(&line)?;
+    file.sync_data()?;
+
+    catch_up(log_path, &mut index)?;
+    if index.lines >= COMPACT_LINES && index.lines > 2 * index.notes.len() {
+        return compact(log_path).context("The note is written, but the log is not compacted");
+    }
+    return save_index(log_path, &index).context("The note is written, but the index of the log is not updated");
+}
+
+/// Rewrites the log with the latest of every note, unless any line cannot be read, which is kept as it is.
+fn compact(log_path: &Path) -> anyhow::Result<()> {
+    let listing = read_all(log_path)?;
+    if !listing.skipped.is_empty() {
+        return save_index(log_path, &indexed(log_path)?);
+    }
+    return rewrite(log_path, &listing.notes);
+}
+
+/// Replaces the log with a line per note, dropping every superseded line.
+pub fn rewrite(log_path: &Path, notes: &[Note]) -> anyhow::Result<()> {
+    let mut content = Vec::new();
+    let mut index = LogIndex::default();
+    for note in notes {
+        let offset = content.len() as u64;
+        serde_json::to_writer(&mut content, note)?;
+        index.notes.insert(note.id.clone(), (offset, content.len() as u64 - offset));
+        content.push(b'\n');
+        index.lines += 1;
+    }
+    index.length = content.len() as u64;
+    // an index of the old log must not outlive it, even if the rewrite is interrupted.
+    remove_index(log_path)?;
+    write_atomically(log_path, &content)?;
+    return save_index(log_path, &index);
+}
+
+/// Tells whether any line is written in an older format.
+pub fn outdated(log_path: &Path) -> anyhow::Result<bool> {
+    for record in parse_records(log_path)?.into_iter().flatten() {
+        if version_of(&record)? < NOTE_VERSION {
+            return Ok(true);
+        }
+    }
+    return Ok(false);
+}
+
+/// Every complete line of the log as json, where a line which is not json fails alone.
+/// An absent log is empty, as notes are created lazily.
+fn parse_records(log_path: &Path) -> anyhow::Result<Vec<anyhow::Result<Value>>> {
+    let content = match fs::read_to_string(log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let complete = match content.rfind('\n') {
+        Some(end) => &content[..end],
+        None => return Ok(Vec::new()),
+    };
+    let records = complete.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow::Error::new(e).context(format!("line {} is not a note", index + 1)))
+                .map_err(|e| e.context(NoteError::Corrupt(log_path.to_path_buf())))
+        })
+        .collect();
+    return Ok(records);
+}
+
+fn is_deleted(record: &Value) -> bool {
+    record.get("deleted").and_then(|deleted| deleted.as_bool()).unwrap_or(false)
+}
+
+fn id_of(record: &Value, log_path: &Path) -> anyhow::Result<String> {
+    return record.get("id")
+        .and_then(|id| id.as_str())
+        .map(|id| id.to_string())
+        .ok_or_else(|| NoteError::Corrupt(log_path.to_path_buf()).into());
+}
+
+/// Drops what follows the last complete line, which ends at `complete`.
+fn drop_torn_tail(log_path: &Path, complete: u64) -> anyhow::Result<()> {
+    let file = match OpenOptions::new().write(true).open(log_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if file.metadata()?.len() > complete {
+        file.set_len(complete)
+            .with_context(|| format!("failed to drop a torn line of {}", log_path.display()))?;
+    }
+    return Ok(());
+}
+
+/// Where the last line of every note starts and how long it is, as of the first `length` bytes of the log.
+/// A deleted note is not indexed, and `lines` counts every line including superseded ones.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LogIndex {
+    length: u64,
+    lines: usize,
+    notes: HashMap<String, (u64, u64)>,
+}
+
+fn index_path(log_path: &Path) -> PathBuf {
+    return log_path.with_extension("index");
+}
+
+/// The index, caught up with every complete line of the log.
+fn indexed(log_path: &Path) -> anyhow::Result<LogIndex> {
+    // an index which cannot be read is rebuilt, as it is only a cache.
+    let mut index = fs::read(index_path(log_path)).ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default();
+    catch_up(log_path, &mut index)?;
+    return Ok(index);
+}
+
+/// Indexes lines after `index.length`, or every line if the log doesn't end a line there.
+fn catch_up(log_path: &Path, index: &mut LogIndex) -> anyhow::Result<()> {
+    let mut file = match File::open(log_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            *index = LogIndex::default();
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !ends_line_at(&mut file, index.length)? {
+        *index = LogIndex::default();
+    }
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(index.length))?;
+    file.read_to_end(&mut tail)?;
+    let complete = tail.iter().rposition(|b| *b == b'\n').map_or(0, |end| end + 1);
+
+    let mut offset = index.length;
+    for line in tail[..complete].split_inclusive(|b| *b == b'\n') {
+        let content = &line[..line.len() - 1];
+        if !content.iter().all(u8::is_ascii_whitespace) {
+            index.lines += 1;
+            let record = serde_json::from_slice::<Value>(content).ok();
+            if let Some((id, record)) = record.and_then(|record| Some((id_of(&record, log_path).ok()?, record))) {
+                if is_deleted(&record) {
+                    index.notes.remove(&id);
+                } else {
+                    index.notes.insert(id, (offset, content.len() as u64));
+                }
+            }
+        }
+        offset += line.len() as u64;
+    }
+    index.length = offset;
+    return Ok(());
+}
+
+fn ends_line_at(file: &mut File, length: u64) -> anyhow::Result<bool> {
+    if length == 0 {
+        return Ok(true);
+    }
+    if file.metadata()?.len() < length {
+        return Ok(false);
+    }
+    let mut last = [0u8];
+    file.seek(SeekFrom::Start(length - 1))?;
+    file.read_exact(&mut last)?;
+    return Ok(last[0] == b'\n');
+}
+
+fn read_line_at(log_path: &Path, offset: u64, length: u64) -> Option<Value> {
+    let mut file = File::open(log_path).ok()?;
+    let mut content = vec![0u8; length as usize];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.read_exact(&mut content).ok()?;
+    return serde_json::from_slice(&content).ok();
+}
+
+fn save_index(log_path: &Path, index: &LogIndex) -> anyhow::Result<()> {
+    return write_atomically(&index_path(log_path), &serde_json::to_vec(index)?);
+}
+
+fn remove_index(log_path: &Path) -> anyhow::Result<()> {
+    return match fs::remove_file(index_path(log_path)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::store::log::{append, index_path, read, read_all, remove, COMPACT_LINES};
+    use crate::note::{Message, Note, Scope};
+    use crate::testlib::TestRepo;
+
+    fn note(id: &str, messages: &[&str]) -> Note {
+        let messages = messages.iter()
+            .map(|m| Message::whole(Scope::File, m.to_string()))
+            .collect();
+        Note::from(&id.to_string(), &PathBuf::from(id), messages)
+    }
+
+    #[test]
+    fn last_line_wins() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+
+        // when
+        append(&log_path, &note("a", &["first"]))?;
+        append(&log_path, &note("b", &["other"]))?;
+        append(&log_path, &note("a", &["first", "second"]))?;
+
+        // then
+        let notes = read_all(&log_path)?.notes;
+        assert_eq!(notes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(notes[0].messages.len(), 2);
+        assert_eq!(read(&log_path, &"b".to_string())?.unwrap().messages[0].message, "other");
+        assert!(read(&log_path, &"c".to_string())?.is_none());
+
+        remove(&log_path, &"a".to_string())?;
+        assert!(read(&log_path, &"a".to_string())?.is_none());
+        assert_eq!(read_all(&log_path)?.notes.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn torn_line() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+        append(&log_path, &note("a", &["kept"]))?;
+        // a crash in the middle of appending
+        OpenOptions::new().append(true).open(&log_path)?.write_all(br#"{"version":2,"id":"a","refer"#)?;
+
+        // when
+        let before = read_all(&log_path)?.notes;
+        append(&log_path, &note("b", &["next"]))?;
+        let after = read_all(&log_path)?.notes;
+
+        // then
+        assert_eq!(before.len(), 1);
+        assert_eq!(before[0].messages[0].message, "kept");
+        assert_eq!(after.len(), 2);
+        assert_eq!(fs::read_to_string(&log_path)?.lines().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_corrupt_line() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+        append(&log_path, &note("a", &["kept"]))?;
+        OpenOptions::new().append(true).open(&log_path)?.write_all(b"not a note\n")?;
+        append(&log_path, &note("b", &["after"]))?;
+
+        // when
+        let listing = read_all(&log_path)?;
+
+        // then
+        assert_eq!(listing.notes.len(), 2);
+        assert_eq!(listing.skipped.len(), 1);
+        assert_eq!(listing.skipped[0].0, log_path);
+        assert!(read_all(&log_path)?.complete().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_past_corrupt_line() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+        append(&log_path, &note("a", &["kept"]))?;
+        OpenOptions::new().append(true).open(&log_path)?.write_all(b"not a note\n")?;
+
+        // when
+        append(&log_path, &note("b", &["after"]))?;
+        let a = read(&log_path, &"a".to_string())?;
+        let b = read(&log_path, &"b".to_string())?;
+
+        // then
+        assert_eq!(a.unwrap().messages[0].message, "kept");
+        assert_eq!(b.unwrap().messages[0].message, "after");
+        Ok(())
+    }
+
+    #[test]
+    fn stale_index() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+        append(&log_path, &note("a", &["first"]))?;
+        append(&log_path, &note("b", &["other"]))?;
+        // appended by a writer which doesn't know the index
+        let line = serde_json::to_string(&note("a", &["second"]))?;
+        OpenOptions::new().append(true).open(&log_path)?.write_all(format!("{}\n", line).as_bytes())?;
+
+        // when
+        let caught_up = read(&log_path, &"a".to_string())?;
+        // replaced without the index, which points past the end of the log now
+        fs::write(&log_path, format!("{}\n", serde_json::to_string(&note("b", &["replaced"]))?))?;
+        let rebuilt = read(&log_path, &"b".to_string())?;
+        let gone = read(&log_path, &"a".to_string())?;
+        fs::write(index_path(&log_path), "not an index")?;
+        let unreadable = read(&log_path, &"b".to_string())?;
+
+        // then
+        assert_eq!(caught_up.unwrap().messages[0].message, "second");
+        assert_eq!(rebuilt.unwrap().messages[0].message, "replaced");
+        assert!(gone.is_none());
+        assert_eq!(unreadable.unwrap().messages[0].message, "replaced");
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let log_path = repo.path().join("log");
+        append(&log_path, &note("b", &["other"]))?;
+        append(&log_path, &note("c", &["deleted"]))?;
+        remove(&log_path, &"c".to_string())?;
+
+        // when
+        for i in 0..COMPACT_LINES {
+            append(&log_path, &note("a", &[&format!("edit {}", i)]))?;
+        }
+
+        // then
+        assert!(fs::read_to_string(&log_path)?.lines().count() < COMPACT_LINES);
+        let notes = read_all(&log_path)?.notes;
+        assert_eq!(notes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["b", "a"]);
+        let latest = format!("edit {}", COMPACT_LINES - 1);
+        assert_eq!(read(&log_path, &"a".to_string())?.unwrap().messages[0].message, latest);
+        assert!(read(&log_path, &"c".to_string())?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::note::Note;
+use crate::path::Paths;
+use crate::store::{Listing, NoteStore};
+
+/// Keeps notes in memory only, e.g. to test without any file system.
+/// Notes of every repository are kept together, as the store is not shared with others.
+#[derive(Default)]
+pub struct MemoryStore {
+    notes: RefCell<HashMap<String, Note>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NoteStore for MemoryStore {
+    fn load(&self, _paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        return Ok(self.notes.borrow().get(id).cloned());
+    }
+
+    fn save(&self, _paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        self.notes.borrow_mut().insert(note.id.clone(), note.clone());
+        return Ok(());
+    }
+
+    fn list(&self, _paths: &Paths) -> anyhow::Result<Listing> {
+        return Ok(Listing { notes: self.notes.borrow().values().cloned().collect(), skipped: Vec::new() });
+    }
+
+    fn delete(&self, _paths: &Paths, id: &String) -> anyhow::Result<()> {
+        self.notes.borrow_mut().remove(id);
+        return Ok(());
+    }
+}
@@ -0,0 +1,179 @@
+//! Where notes are kept. [`NoteStore`] only loads and saves notes as they are,
+//! while anchoring messages to the current content is left to [`crate::note::NoteLedger`].
+
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, Storage};
+use crate::error::NoteError;
+use crate::note::Note;
+use crate::path::Paths;
+
+pub mod file;
+pub mod log;
+pub mod memory;
+pub mod tree;
+
+pub use file::FileStore;
+pub use log::LogStore;
+pub use memory::MemoryStore;
+pub use tree::TreeStore;
+
+/// Loads, saves, lists and deletes notes by their id, which is [`Note::get_id`] of the annotated path.
+/// `paths` tells which repository the note belongs to.
+pub trait NoteStore {
+    /// Returns `None` if the note has never been saved. Never writes anything.
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>>;
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()>;
+    /// Every note saved in the repository, in no particular order.
+    /// Notes which cannot be read are skipped, and told in [`Listing::skipped`].
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing>;
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()>;
+
+    /// The file which the note is stored in, to lock against other writers and to watch changes.
+    /// `None` for a store which is not shared with other processes.
+    fn source(&self, _paths: &Paths, _id: &String) -> anyhow::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Rewrites every note stored in an older format, returning how many are rewritten.
+    fn migrate(&self, _paths: &Paths) -> anyhow::Result<usize> {
+        Ok(0)
+    }
+
+    /// Saves every note at once, e.g. moved from another store.
+    fn import(&self, paths: &Paths, notes: &[Note]) -> anyhow::Result<()> {
+        for note in notes {
+            self.save(paths, note)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every note.
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        for note in self.list(paths)?.complete()? {
+            self.delete(paths, &note.id)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> NoteStore for Box<S>
+where
+    S: NoteStore + ?Sized,
+{
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        (**self).load(paths, id)
+    }
+
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        (**self).save(paths, note)
+    }
+
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        (**self).list(paths)
+    }
+
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()> {
+        (**self).delete(paths, id)
+    }
+
+    fn source(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<PathBuf>> {
+        (**self).source(paths, id)
+    }
+
+    fn migrate(&self, paths: &Paths) -> anyhow::Result<usize> {
+        (**self).migrate(paths)
+    }
+
+    fn import(&self, paths: &Paths, notes: &[Note]) -> anyhow::Result<()> {
+        (**self).import(paths, notes)
+    }
+
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        (**self).clear(paths)
+    }
+}
+
+/// Notes listed by [`NoteStore::list`], so that a corrupt note doesn't hide every other one.
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub notes: Vec<Note>,
+    /// files of notes which cannot be read, either corrupt or written by a newer git-note, with the reason.
+    pub skipped: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl Listing {
+    /// Adds a note read from `source`, or skips it if it cannot be read. Other errors, e.g. of the file system, are returned.
+    pub fn push(&mut self, source: &Path, read: anyhow::Result<Note>) -> anyhow::Result<()> {
+        match read {
+            Ok(note) => self.notes.push(note),
+            Err(e) if is_unreadable(&e) => self.skipped.push((source.to_path_buf(), e)),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Every note, or the first reason of skipped ones. For callers which would lose skipped notes otherwise, e.g. converting.
+    pub fn complete(self) -> anyhow::Result<Vec<Note>> {
+        match self.skipped.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(self.notes),
+        }
+    }
+}
+
+fn is_unreadable(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<NoteError>(), Some(NoteError::Corrupt(_) | NoteError::UnsupportedVersion(_)))
+}
+
+/// Creates the [`NoteStore`] of given storage in `.git/notes`.
+pub fn store_of(storage: Storage) -> Box<dyn NoteStore> {
+    match storage {
+        Storage::Files => Box::new(FileStore),
+        Storage::Log => Box::new(LogStore),
+        Storage::Tree => Box::new(TreeStore),
+    }
+}
+
+/// The store which `storage` in config of each repository points to, which is the default of [`crate::repository::NoteRepository`].
+pub struct ConfiguredStore;
+
+impl ConfiguredStore {
+    fn of(&self, paths: &Paths) -> anyhow::Result<Box<dyn NoteStore>> {
+        Ok(store_of(Config::load(paths)?.storage()))
+    }
+}
+
+impl NoteStore for ConfiguredStore {
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        self.of(paths)?.load(paths, id)
+    }
+
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        self.of(paths)?.save(paths, note)
+    }
+
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        self.of(paths)?.list(paths)
+    }
+
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()> {
+        self.of(paths)?.delete(paths, id)
+    }
+
+    fn source(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<PathBuf>> {
+        self.of(paths)?.source(paths, id)
+    }
+
+    fn migrate(&self, paths: &Paths) -> anyhow::Result<usize> {
+        self.of(paths)?.migrate(paths)
+    }
+
+    fn import(&self, paths: &Paths, notes: &[Note]) -> anyhow::Result<()> {
+        self.of(paths)?.import(paths, notes)
+    }
+
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        self.of(paths)?.clear(paths)
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::error::NoteError;
+use crate::lock::NoteLock;
+use crate::migration::{migrate_from, version_of};
+use crate::note::{Note, NOTE_VERSION};
+use crate::path::Paths;
+use crate::store::{Listing, NoteStore};
+use crate::utils::write_atomically;
+
+const EXTENSION: &str = "yml";
+
+/// A pretty yaml file per annotated path, which mirrors the source tree, which is [`crate::config::Storage::Tree`].
+/// e.g. the note of `src/main.rs` is `.git/notes/tree/src/main.rs.yml`, and the one of the root directory is `.git/notes/tree/.yml`.
+///
+/// Every message is a block of its own, so that notes can be read by hand, and two writers rarely conflict on merge.
+pub struct TreeStore;
+
+impl NoteStore for TreeStore {
+    fn load(&self, paths: &Paths, id: &String) -> anyhow::Result<Option<Note>> {
+        // the id of a note is the hash of its path, so the path is found only by scanning unless it is of given paths.
+        if &Note::get_id(&paths.relative())? != id {
+            return Ok(self.list(paths)?.notes.into_iter().find(|note| &note.id == id));
+        }
+        let tree_path = tree_path(paths, &paths.relative());
+        if !tree_path.exists() {
+            return Ok(None);
+        }
+        return Ok(Some(read_tree_file(&tree_path)?));
+    }
+
+    fn save(&self, paths: &Paths, note: &Note) -> anyhow::Result<()> {
+        let content = serde_yaml_ng::to_string(note)?;
+        let tree_path = tree_path(paths, &note.reference);
+        paths.initialize(&tree_path)?;
+        return write_atomically(&tree_path, content.as_bytes());
+    }
+
+    fn list(&self, paths: &Paths) -> anyhow::Result<Listing> {
+        let mut listing = Listing::default();
+        for tree_path in tree_files(&paths.tree())? {
+            listing.push(&tree_path, read_tree_file(&tree_path))?;
+        }
+        return Ok(listing);
+    }
+
+    fn delete(&self, paths: &Paths, id: &String) -> anyhow::Result<()> {
+        if let Some(note) = self.load(paths, id)? {
+            fs::remove_file(tree_path(paths, &note.reference))?;
+        }
+        return Ok(());
+    }
+
+    fn source(&self, paths: &Paths, _id: &String) -> anyhow::Result<Option<PathBuf>> {
+        return Ok(Some(tree_path(paths, &paths.relative())));
+    }
+
+    fn migrate(&self, paths: &Paths) -> anyhow::Result<usize> {
+        let mut migrated = 0;
+        for tree_path in tree_files(&paths.tree())? {
+            let _lock = NoteLock::acquire(&tree_path)?;
+            let value = read_value(&tree_path)?;
+            if version_of(&value)? >= NOTE_VERSION {
+                continue;
+            }
+            self.save(paths, &migrate_from(value, &tree_path)?)?;
+            migrated += 1;
+        }
+        return Ok(migrated);
+    }
+
+    fn clear(&self, paths: &Paths) -> anyhow::Result<()> {
+        if paths.tree().exists() {
+            fs::remove_dir_all(paths.tree())?;
+        }
+        return Ok(());
+    }
+}
+
+/// `reference` is relative from root of the repository, which is empty for the root directory itself.
+fn tree_path(paths: &Paths, reference: &Path) -> PathBuf {
+    // joining an empty reference leaves a trailing separator, so the root directory becomes `tree/.yml`.
+    let mut tree_path = paths.tree().join(reference).into_os_string();
+    tree_path.push(".");
+    tree_path.push(EXTENSION);
+    return PathBuf::from(tree_path);
+}
+
+/// Every note file under the directory, skipping locks and temporary files of interrupted writes.
+fn tree_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.exists() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(tree_files(&path)?);
+        } else if is_tree_file(&path) {
+            files.push(path);
+        }
+    }
+    return Ok(files);
+}
+
+// `Path::extension` is none for `.yml` of the root directory, so the name is compared instead.
+fn is_tree_file(path: &Path) -> bool {
+    return path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(&format!(".{}", EXTENSION)));
+}
+
+fn read_tree_file(tree_path: &Path) -> anyhow::Result<Note> {
+    let value = read_value(tree_path)?;
+    return migrate_from(value, tree_path);
+}
+
+fn read_value(tree_path: &Path) -> anyhow::Result<Value> {
+    let content = fs::read_to_string(tree_path)?;
+    return serde_yaml_ng::from_str(&content)
+        .map_err(|e| anyhow::Error::new(e).context(NoteError::Corrupt(tree_path.to_path_buf())));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::note::{Message, Note, Scope};
+    use crate::path::PathResolver;
+    use crate::store::{NoteStore, TreeStore};
+    use crate::testlib::TestRepo;
+
+    #[test]
+    fn mirrors_source_tree() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_dir("src")?;
+        repo.create_file("src/main.rs", Some("fn main() {}"))?;
+        let paths = PathResolver::resolve(repo.path(), "src/main.rs")?;
+        let root = PathResolver::resolve(repo.path(), ".")?;
+        let id = Note::get_id(&paths.relative())?;
+        let messages = vec![
+            Message::whole(Scope::File, "entry point".to_string()),
+            Message::whole(Scope::File, "first line\nsecond line".to_string()),
+        ];
+
+        // when
+        TreeStore.save(&paths, &Note::from(&id, &paths.relative(), messages))?;
+        TreeStore.save(&root, &Note::from(&Note::get_id(&root.relative())?, &root.relative(), vec![]))?;
+
+        // then
+        let tree_path = repo.path().join(".git/notes/tree/src/main.rs.yml");
+        let yaml = fs::read_to_string(&tree_path)?;
+        assert!(yaml.contains("reference: src/main.rs\n"), "{}", yaml);
+        assert!(yaml.contains("- uuid: "), "{}", yaml);
+        assert!(yaml.contains("  message: |-\n    first line\n    second line\n"), "{}", yaml);
+        assert!(repo.path().join(".git/notes/tree/.yml").exists());
+
+        let loaded = TreeStore.load(&paths, &id)?.unwrap();
+        assert_eq!(loaded.messages[1].message, "first line\nsecond line");
+        assert_eq!(TreeStore.load(&root, &id)?.unwrap().reference, paths.relative());
+        assert_eq!(TreeStore.list(&root)?.notes.len(), 2);
+
+        TreeStore.delete(&root, &id)?;
+        assert!(!tree_path.exists());
+        assert!(TreeStore.load(&paths, &id)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn skip_corrupt_file() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        repo.create_file("a.txt", Some("a"))?;
+        let paths = PathResolver::resolve(repo.path(), "a.txt")?;
+        TreeStore.save(&paths, &Note::new(&Note::get_id(&paths.relative())?, &paths.relative()))?;
+        let corrupt = repo.path().join(".git/notes/tree/b.txt.yml");
+        fs::write(&corrupt, "messages: [")?;
+
+        // when
+        let listing = TreeStore.list(&paths)?;
+
+        // then
+        assert_eq!(listing.notes.len(), 1);
+        assert_eq!(listing.skipped.len(), 1);
+        assert_eq!(listing.skipped[0].0, corrupt);
+        Ok(())
+    }
+}
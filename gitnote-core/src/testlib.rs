@@ -1,14 +1,22 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::SystemTime;
 use std::str::from_utf8;
 use std::string::ToString;
 
 use anyhow::{anyhow, Error};
 use tempfile::tempdir_in;
 
-use crate::note::Note;
+use crate::config::Charset;
+use crate::diff::{Differ, SimilarDiffer};
+use crate::handlers::{NoteArgs, NoteQuery};
+use crate::libgit::{GitBlob, Libgit};
+use crate::note::{Note, Scope};
+use crate::path::Paths;
 
 pub struct TestRepo {
     _dir: tempfile::TempDir, // holds tempdir ref to delay cleanup
@@ -44,6 +52,20 @@ impl TestRepo {
         }).ok_or(anyhow!(stdout))
     }
 
+    /// Commits every change in the working tree, which returns the id of commit.
+    pub fn commit(&self, message: &str) -> anyhow::Result<String> {
+        self.command("git add --all")?;
+        let output = Command::new("git")
+            .args(["-c", "user.name=tester", "-c", "user.email=tester@localhost", "commit", "--quiet", "--allow-empty", "-m", message])
+            .current_dir(self.path.clone())
+            .output()?;
+        if !output.status.success() {
+            return Err(anyhow!(from_utf8(&output.stderr)?.to_string()));
+        }
+        let head = Command::new("git").args(["rev-parse", "HEAD"]).current_dir(self.path.clone()).output()?;
+        Ok(from_utf8(&head.stdout)?.trim().to_string())
+    }
+
     pub fn create_dir(&self, dirname: &str) -> anyhow::Result<PathBuf> {
         let path = self.path.join(dirname);
         std::fs::create_dir(&path)?;
@@ -74,6 +96,106 @@ impl TestRepo {
     pub fn path(&self) -> PathBuf {
         self.path.clone()
     }
+
+    /// Every file under `.git` with its length and modified time, to tell whether anything is written there.
+    pub fn git_files(&self) -> anyhow::Result<BTreeMap<PathBuf, (u64, SystemTime)>> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![self.path.join(".git")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                }
+                files.insert(entry.path(), (metadata.len(), metadata.modified()?));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Makes `.git/notes` and everything in it read-only, or writable again.
+    #[cfg(unix)]
+    pub fn set_notes_readonly(&self, readonly: bool) -> anyhow::Result<()> {
+        let mode = if readonly { "a-w" } else { "u+w" };
+        self.command(&format!("chmod -R {} .git/notes", mode))?;
+        Ok(())
+    }
+}
+
+/// [`NoteArgs`] of tests, which targets the line if given, or the whole file otherwise.
+/// The rest are set by struct update syntax, e.g. `TestArgs { tags, ..TestArgs::line(&paths, 1, "hello") }`.
+#[derive(Debug, Clone)]
+pub struct TestArgs {
+    pub paths: Paths,
+    /// starts from 1
+    pub line: Option<usize>,
+    pub message: String,
+    pub content: Option<String>,
+    pub tags: Vec<String>,
+    pub author: Option<String>,
+    pub query: NoteQuery,
+}
+
+impl TestArgs {
+    /// On the line of given paths, which starts from 1.
+    pub fn line(paths: &Paths, line: usize, message: &str) -> Self {
+        TestArgs { line: Some(line), ..Self::file(paths, message) }
+    }
+
+    /// On the whole file, or directory, of given paths.
+    pub fn file(paths: &Paths, message: &str) -> Self {
+        TestArgs {
+            paths: paths.clone(),
+            line: None,
+            message: message.to_string(),
+            content: None,
+            tags: Vec::new(),
+            author: None,
+            query: NoteQuery::default(),
+        }
+    }
+}
+
+impl NoteArgs for TestArgs {
+    fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    fn user_line(&self) -> usize {
+        self.line.expect("line is not given for a file note")
+    }
+
+    fn sys_line(&self) -> usize {
+        self.user_line() - 1
+    }
+
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn scope(&self) -> Scope {
+        match self.line {
+            Some(_) => Scope::Line,
+            None => Scope::File,
+        }
+    }
+
+    fn content(&self) -> Option<String> {
+        self.content.clone()
+    }
+
+    fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+
+    fn author(&self) -> Option<String> {
+        self.author.clone()
+    }
+
+    fn query(&self) -> NoteQuery {
+        self.query.clone()
+    }
 }
 
 pub trait AnyToString {
@@ -85,3 +207,68 @@ impl AnyToString for PathBuf {
         self.to_str().unwrap().to_string()
     }
 }
+
+/// A [`Libgit`] over contents in memory, which needs neither git repository nor file system.
+/// Use it with [`crate::store::MemoryStore`] to test [`crate::handlers::NoteHandler`] alone.
+pub struct FakeLibgit {
+    differ: SimilarDiffer,
+    files: RefCell<HashMap<PathBuf, String>>,
+    blobs: RefCell<HashMap<String, String>>,
+}
+
+impl FakeLibgit {
+    pub fn new() -> Self {
+        FakeLibgit {
+            differ: SimilarDiffer,
+            files: RefCell::new(HashMap::new()),
+            blobs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Changes the content of the file, given relative from root of the repository.
+    pub fn write(&self, path: &str, content: &str) {
+        self.files.borrow_mut().insert(PathBuf::from(path), content.to_string());
+    }
+
+    fn blob(&self, paths: &Paths, content: &String, persist: bool) -> GitBlob {
+        let id = sha1_smol::Sha1::from(content.as_bytes()).digest().to_string();
+        if persist {
+            self.blobs.borrow_mut().insert(id.clone(), content.clone());
+        }
+        GitBlob { id, file_path: paths.relative(), content: content.clone() }
+    }
+}
+
+impl Libgit for FakeLibgit {
+    fn make_git_blob(&self, paths: &Paths, persist: bool) -> anyhow::Result<GitBlob> {
+        let content = self.files.borrow().get(&paths.relative()).cloned()
+            .ok_or(anyhow!("no such file : {}", paths.relative().display()))?;
+        Ok(self.blob(paths, &content, persist))
+    }
+
+    fn make_git_blob_of(&self, paths: &Paths, content: &String, persist: bool) -> anyhow::Result<GitBlob> {
+        Ok(self.blob(paths, content, persist))
+    }
+
+    fn read_git_blob_as(&self, paths: &Paths, oid: &String, _charset: Charset) -> anyhow::Result<GitBlob> {
+        let content = self.blobs.borrow().get(oid).cloned()
+            .ok_or(anyhow!("no such blob : {}", oid))?;
+        Ok(GitBlob { id: oid.clone(), file_path: paths.relative(), content })
+    }
+
+    fn differ(&self) -> &dyn Differ {
+        &self.differ
+    }
+
+    fn retain_blobs(&self, _paths: &Paths, _oids: &Vec<String>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn retained_blobs(&self, _paths: &Paths) -> anyhow::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn is_binary(&self, paths: &Paths) -> anyhow::Result<bool> {
+        Ok(self.files.borrow().get(&paths.relative()).is_some_and(|content| content.contains('\0')))
+    }
+}
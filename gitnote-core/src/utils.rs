@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::Context;
 
@@ -46,6 +47,50 @@ where
     Ok(file)
 }
 
+/// Replaces the file with given content, so that the file has either the old or the new content even if it crashes midway.
+/// The content goes to a temporary file next to it first, which is synced and then renamed over the file.
+/// Permissions of the file are kept, e.g. the executable bit of a script.
+pub fn write_atomically(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = path.parent().with_context(|| format!("{} has no parent directory", path.display()))?;
+    let name = path.file_name().with_context(|| format!("{} has no file name", path.display()))?;
+    // unique among processes, and threads of a long-running one such as the IDE.
+    let temp = dir.join(format!(
+        "{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(content)?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&temp, path)?;
+        sync_dir(dir)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    return result.with_context(|| format!("failed to write {}", path.display()));
+}
+
+/// Makes the rename durable, which is recorded in the directory.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> anyhow::Result<()> {
+    Ok(File::open(dir)?.sync_all()?)
+}
+
+/// Directories can't be opened on windows, where a rename is flushed by the file system.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
 /// A simple trait which writes self to a file
 pub trait Writeable {
     fn write(&self, file: &mut File) -> anyhow::Result<()>;
@@ -67,4 +112,48 @@ impl Writeable for Vec<u8> {
     fn write(&self, file: &mut File) -> anyhow::Result<()> {
         Ok(file.write_all(self)?)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::testlib::TestRepo;
+    use crate::utils::write_atomically;
+
+    #[test]
+    fn write_atomically_replaces() -> anyhow::Result<()> {
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("note", Some("old"))?;
+
+        // when
+        write_atomically(&path, b"new")?;
+
+        // then
+        assert_eq!(fs::read_to_string(&path)?, "new");
+        let leftovers = fs::read_dir(repo.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_keeps_permissions() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        // given
+        let repo = TestRepo::new();
+        let path = repo.create_file("run.sh", Some("echo old"))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+
+        // when
+        write_atomically(&path, b"echo new")?;
+
+        // then
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o755);
+        Ok(())
+    }
+}
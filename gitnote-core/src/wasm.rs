@@ -0,0 +1,61 @@
+//! Javascript bindings, built with `wasm` feature for a browser-based note viewer.
+//!
+//! ```js
+//! import init, { reanchor } from "./pkg/gitnote.js";
+//!
+//! await init();
+//! const messages = JSON.parse(reanchor(oldContent, newContent, JSON.stringify(note.messages)));
+//! ```
+//!
+//! The viewer fetches blobs by itself, so nothing here touches git or file system.
+
+use wasm_bindgen::prelude::*;
+
+use crate::diff::SimilarDiffer;
+use crate::note::{Message, Scope};
+
+/// Re-anchors `messages`, which is a JSON array of messages written against `old` content, to `new` content.
+/// Returns a JSON array of messages which are still valid, with their lines moved.
+/// Messages which are not about a single line are always kept as they are.
+#[wasm_bindgen]
+pub fn reanchor(old: &str, new: &str, messages: &str) -> Result<String, JsError> {
+    let messages: Vec<Message> = serde_json::from_str(messages)?;
+    let reanchored = reanchor_messages(&old.to_string(), &new.to_string(), &messages);
+    Ok(serde_json::to_string(&reanchored)?)
+}
+
+// `JsError` is only available on wasm32, so the logic stays apart from it to be tested natively.
+fn reanchor_messages(old: &String, new: &String, messages: &[Message]) -> Vec<Message> {
+    messages.iter()
+        .filter_map(|m| match m.scope {
+            Scope::Line => m.reanchor(&SimilarDiffer, old, new)
+                .map(|line| m.copied(line, m.oid.clone())),
+            _ => Some(m.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::note::Message;
+    use crate::wasm::reanchor_messages;
+
+    #[test]
+    fn follows_lines() -> anyhow::Result<()> {
+        // given
+        let messages = r#"[
+            {"uuid": "a", "oid": "1", "line": 0, "snippet": "foo", "message": "on foo", "created_at": "2024-05-01T00:00:00Z", "updated_at": "2024-05-01T00:00:00Z"},
+            {"uuid": "b", "oid": "1", "line": 1, "snippet": "bar", "message": "on bar", "created_at": "2024-05-01T00:00:00Z", "updated_at": "2024-05-01T00:00:00Z"},
+            {"uuid": "c", "oid": "1", "line": 0, "snippet": "", "message": "on file", "created_at": "2024-05-01T00:00:00Z", "updated_at": "2024-05-01T00:00:00Z", "scope": "file"}
+        ]"#;
+        let messages: Vec<Message> = serde_json::from_str(messages)?;
+
+        // when
+        let reanchored = reanchor_messages(&"foo\nbar\nbaz".to_string(), &"new\nfoo\nqux\nbaz".to_string(), &messages);
+
+        // then
+        let reanchored = reanchored.iter().map(|m| (m.uuid.as_str(), m.line)).collect::<Vec<_>>();
+        assert_eq!(reanchored, vec![("a", 1), ("c", 0)]);
+        Ok(())
+    }
+}
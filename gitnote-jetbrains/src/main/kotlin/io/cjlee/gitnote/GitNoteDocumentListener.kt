@@ -75,18 +75,27 @@ class GitNoteDocumentListener(
 
     override fun documentChanged(event: DocumentEvent) {
         this.queue(LOW_PRIORITY) {
-            FileDocumentManager.getInstance().saveDocument(event.document)
+            // anchors follow the unsaved text, so that the document does not need to be saved on every change.
+            note = handler.read(file.path, force = true, content = unsavedContent()) ?: throw IllegalStateException("no note")
             addMessageIcons(event.document)
         }
     }
 
     private fun reload() {
         this.queue(HIGH_PRIORITY) {
-            note = handler.read(file.path, force = true) ?: throw IllegalStateException("no note")
+            note = handler.read(file.path, force = true, content = unsavedContent()) ?: throw IllegalStateException("no note")
             addMessageIcons(editor.document)
         }
     }
 
+    private fun unsavedContent(): String? {
+        val document = editor.document
+        if (!FileDocumentManager.getInstance().isDocumentUnsaved(document)) {
+            return null
+        }
+        return document.text
+    }
+
     private fun addMessageIcons(document: Document) {
         editor.markupModel.removeAllHighlighters()
         val messagesByLine = note.messages.groupBy { it.line }
@@ -139,10 +148,10 @@ class GitNoteDocumentListener(
                 override fun handle(data: Any?): ProtocolHandler.Response {
                     val protocolMessage = mapper.convertValue<ProtocolMessaage>(data!!)
                     if (protocolMessage.message.isEmpty()) {
-                        handler.delete(file.path, protocolMessage.line)
+                        handler.delete(file.path, protocolMessage.line, unsavedContent())
                         return ProtocolHandler.Response()
                     }
-                    val response = handler.add(file.path, protocolMessage.line, protocolMessage.message)
+                    val response = handler.add(file.path, protocolMessage.line, protocolMessage.message, unsavedContent())
                     if (response.isSuccess) {
                         reloadOnEventThread()
                         return ProtocolHandler.Response()
@@ -154,11 +163,11 @@ class GitNoteDocumentListener(
                 override fun handle(data: Any?): ProtocolHandler.Response {
                     val protocolMessage = mapper.convertValue<ProtocolMessaage>(data!!)
                     if (protocolMessage.message.isEmpty()) {
-                        handler.delete(file.path, protocolMessage.line)
+                        handler.delete(file.path, protocolMessage.line, unsavedContent())
                         return ProtocolHandler.Response()
                     }
 
-                    val response = handler.update(file.path, protocolMessage.line, protocolMessage.message)
+                    val response = handler.update(file.path, protocolMessage.line, protocolMessage.message, unsavedContent())
                     if (response.isSuccess) {
                         reloadOnEventThread()
                         return ProtocolHandler.Response()
@@ -169,7 +178,7 @@ class GitNoteDocumentListener(
             "messages/delete" to object : ProtocolHandler {
                 override fun handle(data: Any?): ProtocolHandler.Response {
                     val message = mapper.convertValue<ProtocolMessaage>(data!!)
-                    val deleteResponse = handler.delete(file.path, message.line)
+                    val deleteResponse = handler.delete(file.path, message.line, unsavedContent())
                     if (!deleteResponse.isSuccess) {
                         return ProtocolHandler.Response(error = "Failed to delete message : ${deleteResponse.text}")
                     }
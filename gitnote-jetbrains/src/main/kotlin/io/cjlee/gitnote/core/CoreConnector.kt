@@ -2,11 +2,15 @@ package io.cjlee.gitnote.core
 
 import com.fasterxml.jackson.annotation.JsonAlias
 
+/**
+ * `content` is the text of the document which is not saved yet.
+ * If given, lines are anchored to it instead of the file on disk.
+ */
 interface CoreConnector {
-    fun add(filePath: String, line: Int, message: String): Response
-    fun read(filePath: String): Response
-    fun update(filePath: String, line: Int, message: String): Response
-    fun delete(filePath: String, line: Int): Response
+    fun add(filePath: String, line: Int, message: String, content: String? = null): Response
+    fun read(filePath: String, content: String? = null): Response
+    fun update(filePath: String, line: Int, message: String, content: String? = null): Response
+    fun delete(filePath: String, line: Int, content: String? = null): Response
 
     data class Response(
         @JsonAlias("exit_code")
@@ -13,11 +13,14 @@ class CoreHandler(private val connector: CoreConnector) {
     // TODO : maybe cache would not be required, thanks to MergingUpdateQueue
     private val cache = NoteCache()
 
-    fun read(filePath: String, force: Boolean = false): Note? {
+    /**
+     * @param content text of the document which is not saved yet, to anchor lines to instead of the file on disk.
+     */
+    fun read(filePath: String, force: Boolean = false, content: String? = null): Note? {
         if (force) {
-            return cache.put(filePath, read0(filePath))
+            return cache.put(filePath, read0(filePath, content))
         }
-        return cache.get(filePath) ?: cache.put(filePath, read0(filePath))
+        return cache.get(filePath) ?: cache.put(filePath, read0(filePath, content))
     }
 
     fun readMessages(filePath: String, line: Int, force: Boolean = false): List<Message> {
@@ -25,8 +28,8 @@ class CoreHandler(private val connector: CoreConnector) {
         return note.messages.filter { it.line == line }
     }
 
-    private fun read0(filePath: String): Note? {
-        val response = connector.read(filePath)
+    private fun read0(filePath: String, content: String? = null): Note? {
+        val response = connector.read(filePath, content)
         if (response.isSuccess) {
             return runCatching { mapper.readValue<Note>(response.text) }.getOrNull()
         }
@@ -34,21 +37,21 @@ class CoreHandler(private val connector: CoreConnector) {
     }
 
     // always do read on modification.
-    fun add(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return connector.add(filePath, line + 1, message)
-            .onSuccess { cache.put(filePath, read0(filePath)) }
+    fun add(filePath: String, line: Int, message: String, content: String? = null): CoreConnector.Response {
+        return connector.add(filePath, line + 1, message, content)
+            .onSuccess { cache.put(filePath, read0(filePath, content)) }
     }
 
     // always do read on modification.
-    fun update(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return connector.update(filePath, line + 1, message)
-            .onSuccess { cache.put(filePath, read0(filePath)) }
+    fun update(filePath: String, line: Int, message: String, content: String? = null): CoreConnector.Response {
+        return connector.update(filePath, line + 1, message, content)
+            .onSuccess { cache.put(filePath, read0(filePath, content)) }
     }
 
     // always do read on modification.
-    fun delete(filePath: String, line: Int): CoreConnector.Response {
-        return connector.delete(filePath, line + 1)
-            .onSuccess { cache.put(filePath, read0(filePath)) }
+    fun delete(filePath: String, line: Int, content: String? = null): CoreConnector.Response {
+        return connector.delete(filePath, line + 1, content)
+            .onSuccess { cache.put(filePath, read0(filePath, content)) }
     }
 
     private fun CoreConnector.Response.onSuccess(action: () -> Unit): CoreConnector.Response {
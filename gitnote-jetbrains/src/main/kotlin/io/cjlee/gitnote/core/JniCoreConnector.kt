@@ -27,31 +27,31 @@ class JniCoreConnector(
         System.load(libFile.path)
     }
 
-    override fun add(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return add0(projectPath, filePath, line, message)
+    override fun add(filePath: String, line: Int, message: String, content: String?): CoreConnector.Response {
+        return add0(projectPath, filePath, line, message, content)
             .let { mapper.readValue<CoreConnector.Response>(it) }
     }
 
-    private external fun add0(execPath: String, filePath: String, line: Int, message: String): String
+    private external fun add0(execPath: String, filePath: String, line: Int, message: String, content: String?): String
 
-    override fun read(filePath: String): CoreConnector.Response {
-        return read0(projectPath, filePath)
+    override fun read(filePath: String, content: String?): CoreConnector.Response {
+        return read0(projectPath, filePath, content)
             .let { mapper.readValue<CoreConnector.Response>(it) }
     }
 
-    private external fun read0(execPath: String, filePath: String): String
+    private external fun read0(execPath: String, filePath: String, content: String?): String
 
-    override fun update(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return update0(projectPath, filePath, line, message)
+    override fun update(filePath: String, line: Int, message: String, content: String?): CoreConnector.Response {
+        return update0(projectPath, filePath, line, message, content)
             .let { mapper.readValue<CoreConnector.Response>(it) }
     }
 
-    private external fun update0(execPath: String, filePath: String, line: Int, message: String): String
+    private external fun update0(execPath: String, filePath: String, line: Int, message: String, content: String?): String
 
-    override fun delete(filePath: String, line: Int): CoreConnector.Response {
-        return delete0(projectPath, filePath, line)
+    override fun delete(filePath: String, line: Int, content: String?): CoreConnector.Response {
+        return delete0(projectPath, filePath, line, content)
             .let { mapper.readValue<CoreConnector.Response>(it) }
     }
 
-    private external fun delete0(execPath: String, filePath: String, line: Int): String
+    private external fun delete0(execPath: String, filePath: String, line: Int, content: String?): String
 }
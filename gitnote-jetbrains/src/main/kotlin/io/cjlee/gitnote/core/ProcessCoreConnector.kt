@@ -11,26 +11,29 @@ class ProcessCoreConnector(
         get() = COMMAND
     private val runtime = Runtime.getRuntime()
 
-    override fun add(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return executeCommand("add", "--file", filePath, "--line", "$line", "--message", message)
+    override fun add(filePath: String, line: Int, message: String, content: String?): CoreConnector.Response {
+        return executeCommand(content, "add", "--file", filePath, "--line", "$line", "--message", message)
     }
 
-    override fun read(filePath: String): CoreConnector.Response {
-        return executeCommand("read", "--file", filePath, "--formatted")
+    override fun read(filePath: String, content: String?): CoreConnector.Response {
+        return executeCommand(content, "read", "--file", filePath, "--formatted")
     }
 
-    override fun update(filePath: String, line: Int, message: String): CoreConnector.Response {
-        return executeCommand("edit", "--file", filePath, "--line", "$line", "--message", message)
+    override fun update(filePath: String, line: Int, message: String, content: String?): CoreConnector.Response {
+        return executeCommand(content, "edit", "--file", filePath, "--line", "$line", "--message", message)
     }
 
-    override fun delete(filePath: String, line: Int): CoreConnector.Response {
-        return executeCommand("delete", "--file", filePath, "--line", "$line")
+    override fun delete(filePath: String, line: Int, content: String?): CoreConnector.Response {
+        return executeCommand(content, "delete", "--file", filePath, "--line", "$line")
     }
 
-    private fun executeCommand(vararg command: String): CoreConnector.Response {
+    // unsaved content is passed through stdin, as it can be too large for an argument.
+    private fun executeCommand(content: String?, vararg args: String): CoreConnector.Response {
+        val command = if (content != null) arrayOf(*args, "--stdin") else arrayOf(*args)
         return try {
             val process = runtime.exec(this.command + command, null, File(projectPath))
             System.err.println("execute command : ${(this.command + command).contentToString()}")
+            process.outputStream.use { stdin -> content?.let { stdin.write(it.toByteArray()) } }
             process.waitFor()
             val exitValue = process.exitValue()
             val stream = if (exitValue == 0) process.inputStream else process.errorStream